@@ -0,0 +1,33 @@
+-- Add up migration script here
+CREATE SEQUENCE IF NOT EXISTS order_priority_seq;
+
+CREATE TABLE
+    IF NOT EXISTS orders (
+        id VARCHAR(255) PRIMARY KEY,
+        opinion_id VARCHAR(255) NOT NULL REFERENCES opinions (id),
+        user_id VARCHAR(255) NOT NULL REFERENCES users (id),
+        side VARCHAR(16) NOT NULL,
+        price INTEGER NOT NULL,
+        quantity INTEGER NOT NULL,
+        remaining_quantity INTEGER NOT NULL,
+        status VARCHAR(32) NOT NULL DEFAULT 'open',
+        -- time priority inside a price level, taken again when an order loses its place in the queue
+        priority BIGINT NOT NULL DEFAULT nextval('order_priority_seq'),
+        created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        updated_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_side CHECK (side IN ('favour', 'against'));
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_status CHECK (
+    status IN ('open', 'partially_filled', 'filled', 'cancelled')
+);
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_remaining_quantity CHECK (
+    remaining_quantity >= 0
+    AND remaining_quantity <= quantity
+);
+
+CREATE INDEX IF NOT EXISTS idx_orders_resting ON orders (opinion_id, priority)
+WHERE
+    status IN ('open', 'partially_filled');
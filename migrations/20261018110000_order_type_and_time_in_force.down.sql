@@ -0,0 +1,6 @@
+-- Add down migration script here
+ALTER TABLE orders
+DROP COLUMN time_in_force;
+
+ALTER TABLE orders
+DROP COLUMN order_type;
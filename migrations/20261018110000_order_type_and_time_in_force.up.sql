@@ -0,0 +1,10 @@
+-- Add up migration script here
+ALTER TABLE orders
+ADD COLUMN order_type VARCHAR(16) NOT NULL DEFAULT 'limit';
+
+ALTER TABLE orders
+ADD COLUMN time_in_force VARCHAR(16) NOT NULL DEFAULT 'gtc';
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_type CHECK (order_type IN ('limit', 'market'));
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_time_in_force CHECK (time_in_force IN ('gtc', 'ioc', 'fok'));
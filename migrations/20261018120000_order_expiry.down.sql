@@ -0,0 +1,16 @@
+-- Add down migration script here
+UPDATE orders
+SET
+    status = 'cancelled'
+WHERE
+    status = 'expired';
+
+ALTER TABLE orders
+DROP CONSTRAINT chk_order_status;
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_status CHECK (
+    status IN ('open', 'partially_filled', 'filled', 'cancelled')
+);
+
+ALTER TABLE orders
+DROP COLUMN expires_at;
@@ -0,0 +1,16 @@
+-- Add up migration script here
+ALTER TABLE orders
+ADD COLUMN expires_at TIMESTAMPTZ DEFAULT NULL;
+
+ALTER TABLE orders
+DROP CONSTRAINT chk_order_status;
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_status CHECK (
+    status IN (
+        'open',
+        'partially_filled',
+        'filled',
+        'cancelled',
+        'expired'
+    )
+);
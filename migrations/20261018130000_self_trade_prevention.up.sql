@@ -0,0 +1,12 @@
+-- Add up migration script here
+ALTER TABLE users
+ADD COLUMN self_trade_prevention VARCHAR(32) NOT NULL DEFAULT 'cancel_newest';
+
+ALTER TABLE users ADD CONSTRAINT chk_self_trade_prevention CHECK (
+    self_trade_prevention IN (
+        'cancel_newest',
+        'cancel_oldest',
+        'cancel_both',
+        'decrement_and_cancel'
+    )
+);
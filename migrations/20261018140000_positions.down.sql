@@ -0,0 +1,14 @@
+-- Add down migration script here
+DROP TABLE IF EXISTS positions;
+
+ALTER TABLE trades
+DROP COLUMN against_action;
+
+ALTER TABLE trades
+DROP COLUMN favour_action;
+
+ALTER TABLE orders
+DROP CONSTRAINT chk_order_action;
+
+ALTER TABLE orders
+DROP COLUMN action;
@@ -0,0 +1,74 @@
+-- Add up migration script here
+ALTER TABLE orders
+ADD COLUMN action VARCHAR(8) NOT NULL DEFAULT 'buy';
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_action CHECK (action IN ('buy', 'sell'));
+
+-- favour user is on the YES side of the trade (buying YES or selling NO), against user on the NO side
+ALTER TABLE trades
+ADD COLUMN favour_action VARCHAR(8) NOT NULL DEFAULT 'buy';
+
+ALTER TABLE trades
+ADD COLUMN against_action VARCHAR(8) NOT NULL DEFAULT 'buy';
+
+CREATE TABLE
+    IF NOT EXISTS positions (
+        opinion_id VARCHAR(255) NOT NULL REFERENCES opinions (id),
+        user_id VARCHAR(255) NOT NULL REFERENCES users (id),
+        side VARCHAR(16) NOT NULL,
+        quantity INTEGER NOT NULL DEFAULT 0,
+        -- shares promised to resting sell orders
+        reserved_quantity INTEGER NOT NULL DEFAULT 0,
+        -- part of the user's hold balance paid for these shares, it is spent at resolution or when they are sold
+        locked INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (opinion_id, user_id, side)
+    );
+
+ALTER TABLE positions ADD CONSTRAINT chk_position_side CHECK (side IN ('favour', 'against'));
+
+ALTER TABLE positions ADD CONSTRAINT chk_position_quantity CHECK (
+    quantity >= 0
+    AND reserved_quantity >= 0
+    AND reserved_quantity <= quantity
+    AND locked >= 0
+);
+
+-- every trade so far opened shares on both sides, collateral of each side stays held till resolution
+INSERT INTO
+    positions (opinion_id, user_id, side, quantity, locked)
+SELECT
+    opinion_id,
+    user_id,
+    side,
+    SUM(quantity),
+    SUM(locked)
+FROM
+    (
+        SELECT
+            t.opinion_id,
+            t.favour_user_id AS user_id,
+            'favour' AS side,
+            t.quantity,
+            t.favour_price * t.quantity AS locked
+        FROM
+            trades t
+            JOIN opinions o ON t.opinion_id = o.id
+        WHERE
+            o.result IS NULL
+        UNION ALL
+        SELECT
+            t.opinion_id,
+            t.against_user_id AS user_id,
+            'against' AS side,
+            t.quantity,
+            t.against_price * t.quantity AS locked
+        FROM
+            trades t
+            JOIN opinions o ON t.opinion_id = o.id
+        WHERE
+            o.result IS NULL
+    ) held
+GROUP BY
+    opinion_id,
+    user_id,
+    side;
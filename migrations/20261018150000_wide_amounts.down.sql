@@ -0,0 +1,32 @@
+-- Add down migration script here
+-- the balance log trigger depends on users.balance, it has to go while the column changes type
+DROP TRIGGER IF EXISTS trigger_on_balance_change ON users;
+
+ALTER TABLE positions
+ALTER COLUMN quantity TYPE INTEGER,
+ALTER COLUMN reserved_quantity TYPE INTEGER,
+ALTER COLUMN locked TYPE INTEGER;
+
+ALTER TABLE orders
+ALTER COLUMN price TYPE INTEGER,
+ALTER COLUMN quantity TYPE INTEGER,
+ALTER COLUMN remaining_quantity TYPE INTEGER;
+
+ALTER TABLE trades
+ALTER COLUMN favour_price TYPE INTEGER,
+ALTER COLUMN against_price TYPE INTEGER,
+ALTER COLUMN quantity TYPE INTEGER;
+
+ALTER TABLE user_balance_logs
+ALTER COLUMN old_balance TYPE INTEGER,
+ALTER COLUMN new_balance TYPE INTEGER;
+
+ALTER TABLE users
+ALTER COLUMN balance TYPE INTEGER,
+ALTER COLUMN hold_balance TYPE INTEGER;
+
+CREATE TRIGGER trigger_on_balance_change
+AFTER UPDATE ON users
+FOR EACH ROW
+WHEN (OLD.balance IS DISTINCT FROM NEW.balance)
+EXECUTE FUNCTION on_balance_change();
@@ -0,0 +1,32 @@
+-- Add up migration script here
+-- the balance log trigger depends on users.balance, it has to go while the column changes type
+DROP TRIGGER IF EXISTS trigger_on_balance_change ON users;
+
+ALTER TABLE users
+ALTER COLUMN balance TYPE BIGINT,
+ALTER COLUMN hold_balance TYPE BIGINT;
+
+ALTER TABLE user_balance_logs
+ALTER COLUMN old_balance TYPE BIGINT,
+ALTER COLUMN new_balance TYPE BIGINT;
+
+ALTER TABLE trades
+ALTER COLUMN favour_price TYPE BIGINT,
+ALTER COLUMN against_price TYPE BIGINT,
+ALTER COLUMN quantity TYPE BIGINT;
+
+ALTER TABLE orders
+ALTER COLUMN price TYPE BIGINT,
+ALTER COLUMN quantity TYPE BIGINT,
+ALTER COLUMN remaining_quantity TYPE BIGINT;
+
+ALTER TABLE positions
+ALTER COLUMN quantity TYPE BIGINT,
+ALTER COLUMN reserved_quantity TYPE BIGINT,
+ALTER COLUMN locked TYPE BIGINT;
+
+CREATE TRIGGER trigger_on_balance_change
+AFTER UPDATE ON users
+FOR EACH ROW
+WHEN (OLD.balance IS DISTINCT FROM NEW.balance)
+EXECUTE FUNCTION on_balance_change();
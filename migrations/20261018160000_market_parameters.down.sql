@@ -0,0 +1,21 @@
+-- Add down migration script here
+ALTER TABLE trades
+DROP CONSTRAINT chk_favour_price_positive;
+
+ALTER TABLE trades
+DROP CONSTRAINT chk_against_price_positive;
+
+ALTER TABLE trades ADD CONSTRAINT chk_favour_price_positive CHECK (favour_price > 50);
+
+ALTER TABLE trades ADD CONSTRAINT chk_against_price_positive CHECK (against_price > 50);
+
+ALTER TABLE trades ADD CONSTRAINT chk_combined_price CHECK (favour_price + against_price >= 1000);
+
+ALTER TABLE opinions
+DROP CONSTRAINT chk_opinion_price_grid,
+DROP CONSTRAINT chk_opinion_price_band,
+DROP CONSTRAINT chk_opinion_tick_size,
+DROP COLUMN max_price,
+DROP COLUMN min_price,
+DROP COLUMN tick_size,
+DROP COLUMN payout;
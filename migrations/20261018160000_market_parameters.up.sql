@@ -0,0 +1,36 @@
+-- Add up migration script here
+-- every market has its own payout per winning share and its own price grid,
+-- existing markets keep the values that used to be hard-coded
+ALTER TABLE opinions
+ADD COLUMN payout BIGINT NOT NULL DEFAULT 1000,
+ADD COLUMN tick_size BIGINT NOT NULL DEFAULT 1,
+ADD COLUMN min_price BIGINT NOT NULL DEFAULT 100,
+ADD COLUMN max_price BIGINT NOT NULL DEFAULT 900;
+
+ALTER TABLE opinions ADD CONSTRAINT chk_opinion_tick_size CHECK (tick_size > 0);
+
+ALTER TABLE opinions ADD CONSTRAINT chk_opinion_price_band CHECK (
+    min_price > 0
+    AND min_price <= max_price
+    AND max_price < payout
+);
+
+ALTER TABLE opinions ADD CONSTRAINT chk_opinion_price_grid CHECK (
+    payout % tick_size = 0
+    AND min_price % tick_size = 0
+    AND max_price % tick_size = 0
+);
+
+-- trade prices depend on the market now, the band is checked when the order comes in
+ALTER TABLE trades
+DROP CONSTRAINT chk_favour_price_positive;
+
+ALTER TABLE trades
+DROP CONSTRAINT chk_against_price_positive;
+
+ALTER TABLE trades
+DROP CONSTRAINT chk_combined_price;
+
+ALTER TABLE trades ADD CONSTRAINT chk_favour_price_positive CHECK (favour_price > 0);
+
+ALTER TABLE trades ADD CONSTRAINT chk_against_price_positive CHECK (against_price > 0);
@@ -0,0 +1,14 @@
+-- Add down migration script here
+ALTER TABLE users
+DROP CONSTRAINT chk_user_limits,
+DROP COLUMN max_notional,
+DROP COLUMN max_position,
+DROP COLUMN max_open_orders,
+DROP COLUMN max_order_size;
+
+ALTER TABLE opinions
+DROP CONSTRAINT chk_opinion_limits,
+DROP COLUMN max_notional,
+DROP COLUMN max_position,
+DROP COLUMN max_open_orders,
+DROP COLUMN max_order_size;
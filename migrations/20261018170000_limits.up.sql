@@ -0,0 +1,29 @@
+-- Add up migration script here
+-- risk limits, NULL means the limit doesn't apply
+-- a market keeps the old cap of 5 per order unless it is created with its own
+ALTER TABLE opinions
+ADD COLUMN max_order_size BIGINT DEFAULT 5,
+ADD COLUMN max_open_orders BIGINT,
+ADD COLUMN max_position BIGINT,
+ADD COLUMN max_notional BIGINT;
+
+ALTER TABLE opinions ADD CONSTRAINT chk_opinion_limits CHECK (
+    max_order_size > 0
+    AND max_open_orders > 0
+    AND max_position > 0
+    AND max_notional > 0
+);
+
+-- per account, set by risk and compliance, the stricter of the market and the account limit applies
+ALTER TABLE users
+ADD COLUMN max_order_size BIGINT,
+ADD COLUMN max_open_orders BIGINT,
+ADD COLUMN max_position BIGINT,
+ADD COLUMN max_notional BIGINT;
+
+ALTER TABLE users ADD CONSTRAINT chk_user_limits CHECK (
+    max_order_size > 0
+    AND max_open_orders > 0
+    AND max_position > 0
+    AND max_notional > 0
+);
@@ -0,0 +1,10 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_orders_user_id_created_at;
+
+DROP INDEX IF EXISTS idx_trades_against_order_id;
+
+DROP INDEX IF EXISTS idx_trades_favour_order_id;
+
+ALTER TABLE trades
+DROP COLUMN against_order_id,
+DROP COLUMN favour_order_id;
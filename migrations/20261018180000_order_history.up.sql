@@ -0,0 +1,11 @@
+-- Add up migration script here
+-- the orders on both sides of a trade, trades from before orders were stored have none
+ALTER TABLE trades
+ADD COLUMN favour_order_id VARCHAR(255) REFERENCES orders (id),
+ADD COLUMN against_order_id VARCHAR(255) REFERENCES orders (id);
+
+CREATE INDEX IF NOT EXISTS idx_trades_favour_order_id ON trades (favour_order_id);
+
+CREATE INDEX IF NOT EXISTS idx_trades_against_order_id ON trades (against_order_id);
+
+CREATE INDEX IF NOT EXISTS idx_orders_user_id_created_at ON orders (user_id, created_at);
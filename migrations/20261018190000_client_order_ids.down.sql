@@ -0,0 +1,7 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_orders_user_id_client_order_id;
+
+ALTER TABLE orders
+DROP COLUMN response,
+DROP COLUMN response_code,
+DROP COLUMN client_order_id;
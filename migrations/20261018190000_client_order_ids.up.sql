@@ -0,0 +1,11 @@
+-- Add up migration script here
+-- an id the client picks for its order, unique per user, so a retried request can be recognised
+-- response_code and response keep what the first request got, a retry gets the same answer
+ALTER TABLE orders
+ADD COLUMN client_order_id VARCHAR(64),
+ADD COLUMN response_code SMALLINT,
+ADD COLUMN response TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_orders_user_id_client_order_id ON orders (user_id, client_order_id)
+WHERE
+    client_order_id IS NOT NULL;
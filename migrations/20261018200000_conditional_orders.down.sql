@@ -0,0 +1,24 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_orders_pending;
+
+DROP TABLE IF EXISTS conditional_orders;
+
+-- holds of pending orders are not given back here, cancel them through the api before rolling back
+UPDATE orders
+SET
+    status = 'cancelled'
+WHERE
+    status = 'pending';
+
+ALTER TABLE orders
+DROP CONSTRAINT chk_order_status;
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_status CHECK (
+    status IN (
+        'open',
+        'partially_filled',
+        'filled',
+        'cancelled',
+        'expired'
+    )
+);
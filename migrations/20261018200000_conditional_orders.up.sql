@@ -0,0 +1,34 @@
+-- Add up migration script here
+-- a conditional order is stored with its hold taken as pending, it enters the book once its trigger fires
+ALTER TABLE orders
+DROP CONSTRAINT chk_order_status;
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_status CHECK (
+    status IN (
+        'pending',
+        'open',
+        'partially_filled',
+        'filled',
+        'cancelled',
+        'expired'
+    )
+);
+
+CREATE TABLE
+    IF NOT EXISTS conditional_orders (
+        order_id VARCHAR(255) PRIMARY KEY REFERENCES orders (id),
+        -- last traded price of the outcome the order trades that fires it
+        trigger_price BIGINT NOT NULL,
+        trigger_direction VARCHAR(16) NOT NULL,
+        -- the mode of the request, used once the order is matched
+        self_trade_prevention VARCHAR(32) NOT NULL,
+        triggered_at TIMESTAMPTZ DEFAULT NULL
+    );
+
+ALTER TABLE conditional_orders ADD CONSTRAINT chk_trigger_price_positive CHECK (trigger_price > 0);
+
+ALTER TABLE conditional_orders ADD CONSTRAINT chk_trigger_direction CHECK (trigger_direction IN ('rises_to', 'falls_to'));
+
+CREATE INDEX IF NOT EXISTS idx_orders_pending ON orders (opinion_id, priority)
+WHERE
+    status = 'pending';
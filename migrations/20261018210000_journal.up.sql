@@ -0,0 +1,88 @@
+-- Add up migration script here
+-- append only log of everything that happened to the book of a market, replayed on startup
+CREATE TABLE
+    IF NOT EXISTS journal (
+        opinion_id VARCHAR(255) NOT NULL REFERENCES opinions (id),
+        -- starts at 1 and goes up by one with every entry of the market
+        sequence BIGINT NOT NULL,
+        event VARCHAR(32) NOT NULL,
+        -- the whole event as json
+        payload TEXT NOT NULL,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        PRIMARY KEY (opinion_id, sequence)
+    );
+
+ALTER TABLE journal ADD CONSTRAINT chk_journal_sequence_positive CHECK (sequence > 0);
+
+ALTER TABLE journal ADD CONSTRAINT chk_journal_event CHECK (
+    event IN (
+        'accepted',
+        'fill',
+        'rested',
+        'cancelled',
+        'expired',
+        'resolved'
+    )
+);
+
+-- orders resting right now start the journal, in their queue order, so the first replay finds the book as it is
+INSERT INTO
+    journal (opinion_id, sequence, event, payload)
+SELECT
+    opinion_id,
+    2 * row_number() OVER queue - 1,
+    'accepted',
+    json_build_object(
+        'event',
+        'accepted',
+        'order',
+        json_build_object(
+            'id',
+            id,
+            'user_id',
+            user_id,
+            'quantity',
+            remaining_quantity,
+            'price',
+            price,
+            'side',
+            side,
+            'action',
+            action,
+            'created_at',
+            created_at,
+            'expires_at',
+            expires_at
+        )
+    )::TEXT
+FROM
+    orders
+WHERE
+    status IN ('open', 'partially_filled') WINDOW queue AS (
+        PARTITION BY
+            opinion_id
+        ORDER BY
+            priority
+    )
+UNION ALL
+SELECT
+    opinion_id,
+    2 * row_number() OVER queue,
+    'rested',
+    json_build_object(
+        'event',
+        'rested',
+        'order_id',
+        id,
+        'quantity',
+        remaining_quantity
+    )::TEXT
+FROM
+    orders
+WHERE
+    status IN ('open', 'partially_filled') WINDOW queue AS (
+        PARTITION BY
+            opinion_id
+        ORDER BY
+            priority
+    );
@@ -0,0 +1,13 @@
+-- Add down migration script here
+-- the funding of makers in unresolved markets stays held, resolve those markets before rolling back
+DROP TABLE IF EXISTS amm_pools;
+
+ALTER TABLE opinions
+DROP CONSTRAINT chk_opinion_liquidity;
+
+ALTER TABLE opinions
+DROP CONSTRAINT chk_opinion_trading_mode;
+
+ALTER TABLE opinions
+DROP COLUMN liquidity,
+DROP COLUMN trading_mode;
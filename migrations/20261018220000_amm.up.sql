@@ -0,0 +1,41 @@
+-- Add up migration script here
+-- a market either matches orders in its book or has every order buy from a market maker
+ALTER TABLE opinions
+ADD COLUMN trading_mode VARCHAR(16) NOT NULL DEFAULT 'order_book',
+-- liquidity parameter of the maker in shares, only markets with a maker have one
+ADD COLUMN liquidity BIGINT DEFAULT NULL;
+
+ALTER TABLE opinions ADD CONSTRAINT chk_opinion_trading_mode CHECK (trading_mode IN ('order_book', 'amm'));
+
+ALTER TABLE opinions ADD CONSTRAINT chk_opinion_liquidity CHECK (
+    (
+        trading_mode = 'amm'
+        AND liquidity > 0
+    )
+    OR (
+        trading_mode = 'order_book'
+        AND liquidity IS NULL
+    )
+);
+
+CREATE TABLE
+    IF NOT EXISTS amm_pools (
+        opinion_id VARCHAR(255) PRIMARY KEY REFERENCES opinions (id),
+        -- created the market and funded the maker, the counterparty of every trade with it
+        owner_id VARCHAR(255) NOT NULL REFERENCES users (id),
+        -- held from the owner until resolution, the most the maker can lose
+        funding BIGINT NOT NULL,
+        -- shares of each outcome the maker sold
+        yes_shares BIGINT NOT NULL DEFAULT 0,
+        no_shares BIGINT NOT NULL DEFAULT 0,
+        -- what buyers paid the maker
+        collected BIGINT NOT NULL DEFAULT 0,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+    );
+
+ALTER TABLE amm_pools ADD CONSTRAINT chk_amm_pool_amounts CHECK (
+    funding >= 0
+    AND yes_shares >= 0
+    AND no_shares >= 0
+    AND collected >= 0
+);
@@ -0,0 +1,4 @@
+-- Add up migration script here
+-- admins are made by hand, there is no endpoint that grants it
+ALTER TABLE users
+ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
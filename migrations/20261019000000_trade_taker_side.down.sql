@@ -0,0 +1,3 @@
+-- Add down migration script here
+ALTER TABLE trades
+DROP COLUMN taker_side;
@@ -0,0 +1,9 @@
+-- Add up migration script here
+-- side of the order that took liquidity, the other side is the maker
+-- unknown for trades from before it was recorded
+ALTER TABLE trades
+ADD COLUMN taker_side VARCHAR(16);
+
+ALTER TABLE trades ADD CONSTRAINT chk_trade_taker_side CHECK (
+    taker_side IN ('favour', 'against')
+);
@@ -0,0 +1,6 @@
+-- Add up migration script here
+-- iceberg orders show this much of their quantity at a time, NULL for orders that show all of it
+ALTER TABLE orders
+ADD COLUMN display_quantity BIGINT;
+
+ALTER TABLE orders ADD CONSTRAINT chk_order_display_quantity CHECK (display_quantity > 0);
@@ -0,0 +1,12 @@
+-- Add up migration script here
+-- what the shares of a position were bought for, quantity times trade price, the notional limit counts it
+-- shares bought from a seller lock no collateral, so `locked` alone misses them
+ALTER TABLE positions
+ADD COLUMN cost BIGINT NOT NULL DEFAULT 0;
+
+-- the trade prices of older positions are not kept apart, their collateral is the closest there is
+UPDATE positions
+SET
+    cost = locked;
+
+ALTER TABLE positions ADD CONSTRAINT chk_position_cost CHECK (cost >= 0);
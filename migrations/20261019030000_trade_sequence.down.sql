@@ -0,0 +1,7 @@
+-- Add down migration script here
+DROP INDEX IF EXISTS idx_trades_opinion_id_seq;
+
+ALTER TABLE trades
+DROP COLUMN seq;
+
+DROP SEQUENCE IF EXISTS trade_seq;
@@ -0,0 +1,45 @@
+-- Add up migration script here
+-- trades of one transaction share their timestamp, the sequence keeps the order they were booked in
+CREATE SEQUENCE IF NOT EXISTS trade_seq;
+
+ALTER TABLE trades
+ADD COLUMN seq BIGINT;
+
+-- older trades are numbered by time, ties of one transaction can't be told apart any more
+UPDATE trades t
+SET
+    seq = numbered.seq
+FROM
+    (
+        SELECT
+            id,
+            row_number() OVER (
+                ORDER BY
+                    created_at,
+                    id
+            ) AS seq
+        FROM
+            trades
+    ) numbered
+WHERE
+    t.id = numbered.id;
+
+SELECT
+    setval(
+        'trade_seq',
+        (
+            SELECT
+                COALESCE(MAX(seq), 0) + 1
+            FROM
+                trades
+        ),
+        false
+    );
+
+ALTER TABLE trades
+ALTER COLUMN seq SET DEFAULT nextval('trade_seq'),
+ALTER COLUMN seq SET NOT NULL;
+
+ALTER SEQUENCE trade_seq OWNED BY trades.seq;
+
+CREATE INDEX IF NOT EXISTS idx_trades_opinion_id_seq ON trades (opinion_id, seq);
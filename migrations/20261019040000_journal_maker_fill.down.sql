@@ -0,0 +1,19 @@
+-- Add down migration script here
+-- the book replays the same without them
+DELETE FROM journal
+WHERE
+    event = 'maker_fill';
+
+ALTER TABLE journal
+DROP CONSTRAINT chk_journal_event;
+
+ALTER TABLE journal ADD CONSTRAINT chk_journal_event CHECK (
+    event IN (
+        'accepted',
+        'fill',
+        'rested',
+        'cancelled',
+        'expired',
+        'resolved'
+    )
+);
@@ -0,0 +1,16 @@
+-- Add up migration script here
+-- fills of an order by the market maker, the maker has no order in the book so they don't change it
+ALTER TABLE journal
+DROP CONSTRAINT chk_journal_event;
+
+ALTER TABLE journal ADD CONSTRAINT chk_journal_event CHECK (
+    event IN (
+        'accepted',
+        'fill',
+        'maker_fill',
+        'rested',
+        'cancelled',
+        'expired',
+        'resolved'
+    )
+);
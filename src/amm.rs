@@ -0,0 +1,207 @@
+use std::{f64::consts::LN_2, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    money::{Money, Overflow, Price, Quantity},
+    state::{MarketParams, Side},
+};
+
+/// how orders of a market find their counterparty
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TradingMode {
+    /// orders match each other in the order book
+    #[default]
+    OrderBook,
+    /// every order buys from the market maker of the market, nothing rests in the book
+    Amm,
+}
+
+impl TradingMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TradingMode::OrderBook => "order_book",
+            TradingMode::Amm => "amm",
+        }
+    }
+}
+
+impl FromStr for TradingMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "order_book" => Ok(TradingMode::OrderBook),
+            "amm" => Ok(TradingMode::Amm),
+            _ => Err(format!("Unknown trading mode {}", mode)),
+        }
+    }
+}
+
+/**
+ * logarithmic market scoring rule maker, `yes` and `no` are the shares of each outcome it sold so far
+ * all of them together cost b * ln(e^(yes/b) + e^(no/b)) payouts with the liquidity b,
+ * a larger b moves the price less per share and needs more funding
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Lmsr {
+    pub liquidity: Quantity,
+    pub yes: Quantity,
+    pub no: Quantity,
+}
+
+/// a buy from the maker, every share at the same price
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AmmFill {
+    pub quantity: Quantity,
+    pub price: Price,
+}
+
+/// what buying from the maker would do right now, nothing is held or traded
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quote {
+    pub side: Side,
+    /// shares the maker sells before the price passes the top of the band, at most the ones asked for
+    pub quantity: Quantity,
+    /// per share, `None` if the maker sells nothing
+    pub price: Option<Price>,
+    pub cost: Money,
+    /// price of the next share before and after the buy
+    pub price_before: f64,
+    pub price_after: f64,
+    pub price_impact: f64,
+}
+
+/// the maker of one market with the user who funded it, owned by the market task
+#[derive(Debug, Clone)]
+pub struct MarketMaker {
+    pub owner_id: String,
+    pub lmsr: Lmsr,
+}
+
+impl Lmsr {
+    pub fn new(liquidity: Quantity) -> Self {
+        Self {
+            liquidity,
+            yes: Quantity::ZERO,
+            no: Quantity::ZERO,
+        }
+    }
+
+    /// the most the maker can lose, b * ln 2 payouts rounded up, the owner funds it when the market is created
+    pub fn funding(liquidity: Quantity, payout: Price) -> Result<Money, Overflow> {
+        let funding = (liquidity.get() as f64 * LN_2 * payout.get() as f64).ceil();
+        if funding >= i64::MAX as f64 {
+            return Err(Overflow);
+        }
+        Ok(Money::new(funding as i64))
+    }
+
+    /// shares sold of `side` and of the other outcome
+    fn sold(&self, side: &Side) -> (f64, f64) {
+        let (own, other) = match side {
+            Side::Favour => (self.yes, self.no),
+            Side::Against => (self.no, self.yes),
+        };
+        (own.get() as f64, other.get() as f64)
+    }
+
+    /// chance of `side` as the maker prices it, the chances of both outcomes add up to 1
+    pub fn probability(&self, side: &Side) -> f64 {
+        let (own, other) = self.sold(side);
+        1.0 / (1.0 + ((other - own) / self.liquidity.get() as f64).exp())
+    }
+
+    /// price of the next fraction of a share of `side`
+    pub fn price(&self, side: &Side, payout: Price) -> f64 {
+        self.probability(side) * payout.get() as f64
+    }
+
+    /**
+     * what `quantity` shares of `side` cost in payouts, b * ln(1 - p + p * e^(q/b)) for the current chance p,
+     * written as q + b * ln(p + (1 - p) * e^(-q/b)) so it doesn't overflow for large q
+     */
+    fn cost(&self, side: &Side, quantity: Quantity) -> f64 {
+        let b = self.liquidity.get() as f64;
+        let q = quantity.get() as f64;
+        let p = self.probability(side);
+        q + b * (p + (1.0 - p) * (-q / b).exp()).ln()
+    }
+
+    /// shares of `side` the maker sells before the price of the next one is above `limit`
+    fn shares_until(&self, side: &Side, limit: Price, payout: Price) -> Quantity {
+        // the price is `limit` once own - other = b * ln(limit / (payout - limit))
+        let (own, other) = self.sold(side);
+        let chance = limit.get() as f64 / payout.get() as f64;
+        let target = self.liquidity.get() as f64 * (chance / (1.0 - chance)).ln();
+        let shares = (target - (own - other)).floor();
+        if shares <= 0.0 {
+            return Quantity::ZERO;
+        }
+        Quantity::new(shares.min(i64::MAX as f64) as i64)
+    }
+
+    /**
+     * buys up to `quantity` shares of `side` without the price passing `limit`, all of them at their
+     * average cost rounded up to the tick and never below the band of the market
+     * the rounding only ever goes the way of the maker, so it can't lose more than its funding
+     */
+    pub fn buy(
+        &self,
+        side: &Side,
+        quantity: Quantity,
+        limit: Price,
+        params: &MarketParams,
+    ) -> AmmFill {
+        let payout = params.payout;
+        let tick = params.tick_size.get();
+        let mut quantity = quantity.min(self.shares_until(side, limit, payout));
+        while !quantity.is_zero() {
+            let average = self.cost(side, quantity) * payout.get() as f64 / quantity.get() as f64;
+            let ticks = (average / tick as f64).ceil() as i64;
+            let price = Price::new(ticks * tick).max(params.min_price);
+            if price <= limit {
+                return AmmFill { quantity, price };
+            }
+            // the average is below the limit, this is only floating point error at the edge
+            quantity -= Quantity::new(1);
+        }
+        AmmFill {
+            quantity,
+            price: limit,
+        }
+    }
+
+    /// the maker sold `quantity` more shares of `side`
+    pub fn sell(&mut self, side: &Side, quantity: Quantity) {
+        match side {
+            Side::Favour => self.yes += quantity,
+            Side::Against => self.no += quantity,
+        }
+    }
+
+    /// a buy of `quantity` shares of `side` at any price inside the band
+    pub fn quote(
+        &self,
+        side: &Side,
+        quantity: Quantity,
+        params: &MarketParams,
+    ) -> Result<Quote, Overflow> {
+        let fill = self.buy(side, quantity, params.max_price, params);
+        let mut after = *self;
+        after.sell(side, fill.quantity);
+        let price_before = self.price(side, params.payout);
+        let price_after = after.price(side, params.payout);
+        Ok(Quote {
+            side: side.clone(),
+            quantity: fill.quantity,
+            price: (!fill.quantity.is_zero()).then_some(fill.price),
+            cost: fill.price.checked_mul(fill.quantity)?,
+            price_before,
+            price_after,
+            price_impact: price_after - price_before,
+        })
+    }
+}
@@ -0,0 +1,144 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::trade::TradeModel,
+    money::Price,
+    state::{CreateOrderDto, Order, Side},
+};
+
+/// which way the last traded price has to move for a conditional order to fire
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerDirection {
+    /// fires once the price is at or above the trigger price, a take profit of a sell
+    RisesTo,
+    /// fires once the price is at or below the trigger price, a stop loss of a sell
+    FallsTo,
+}
+
+impl TriggerDirection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TriggerDirection::RisesTo => "rises_to",
+            TriggerDirection::FallsTo => "falls_to",
+        }
+    }
+}
+
+impl FromStr for TriggerDirection {
+    type Err = String;
+
+    fn from_str(direction: &str) -> Result<Self, Self::Err> {
+        match direction {
+            "rises_to" => Ok(TriggerDirection::RisesTo),
+            "falls_to" => Ok(TriggerDirection::FallsTo),
+            _ => Err(format!("Unknown trigger direction {}", direction)),
+        }
+    }
+}
+
+/**
+ * when a conditional order enters the market, `price` is the price of the outcome the order trades,
+ * the YES price for favour orders and the NO price for against orders
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Trigger {
+    pub price: Price,
+    pub direction: TriggerDirection,
+}
+
+impl Trigger {
+    /// whether `last`, the last traded price of the outcome, is at or past the trigger price
+    pub fn fires(&self, last: Price) -> bool {
+        match self.direction {
+            TriggerDirection::RisesTo => last >= self.price,
+            TriggerDirection::FallsTo => last <= self.price,
+        }
+    }
+}
+
+/// an order waiting for its trigger, its hold is taken and it is stored as pending
+#[derive(Debug, Clone)]
+pub struct ConditionalOrder {
+    pub order: Order,
+    /// what the order turns into once it fires, a limit or market order with its time in force
+    pub request: CreateOrderDto,
+    pub trigger: Trigger,
+}
+
+/// the conditional orders of one market and the price it last traded at, owned by the market task
+#[derive(Debug, Clone, Default)]
+pub struct Triggers {
+    /// YES price of the last trade, `None` until the market traded once
+    last_price: Option<Price>,
+    /// oldest first, that is also the order they fire in
+    pending: Vec<ConditionalOrder>,
+}
+
+impl Triggers {
+    pub fn new(last_price: Option<Price>, pending: Vec<ConditionalOrder>) -> Self {
+        Self {
+            last_price,
+            pending,
+        }
+    }
+
+    /// the last traded price of `side`, NO is worth what YES isn't
+    pub fn last_price(&self, side: &Side, payout: Price) -> Option<Price> {
+        self.last_price.map(|price| match side {
+            Side::Favour => price,
+            Side::Against => price.complement(payout),
+        })
+    }
+
+    /// moves the last price to the one of an executed trade
+    pub fn record(&mut self, trade: &TradeModel) {
+        self.last_price = Some(trade.favour_price);
+    }
+
+    pub fn add(&mut self, conditional: ConditionalOrder) {
+        self.pending.push(conditional);
+    }
+
+    pub fn get(&self, order_id: &str) -> Option<&ConditionalOrder> {
+        self.pending
+            .iter()
+            .find(|conditional| conditional.order.id == order_id)
+    }
+
+    pub fn remove(&mut self, order_id: &str) -> Option<ConditionalOrder> {
+        let index = self
+            .pending
+            .iter()
+            .position(|conditional| conditional.order.id == order_id)?;
+        Some(self.pending.remove(index))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ConditionalOrder> {
+        self.pending.iter()
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+
+    /// whether `trigger` fires at the current last price of `side`
+    pub fn fires(&self, trigger: &Trigger, side: &Side, payout: Price) -> bool {
+        self.last_price(side, payout)
+            .is_some_and(|last| trigger.fires(last))
+    }
+
+    /// takes out every order whose trigger fires at the current last price, oldest first
+    pub fn take_fired(&mut self, payout: Price) -> Vec<ConditionalOrder> {
+        let (fired, pending) =
+            std::mem::take(&mut self.pending)
+                .into_iter()
+                .partition(|conditional| {
+                    self.fires(&conditional.trigger, &conditional.order.side, payout)
+                });
+        self.pending = pending;
+        fired
+    }
+}
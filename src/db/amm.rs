@@ -0,0 +1,137 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, Executor, PgPool, Postgres, prelude::FromRow, query, query_as};
+
+use crate::{
+    money::{Money, Overflow, Price, Quantity},
+    state::Side,
+};
+
+#[derive(Clone)]
+pub struct Amm {
+    pool: PgPool,
+}
+
+impl Amm {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// the maker of a new market, nothing sold yet
+    pub async fn create<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &str,
+        owner_id: &str,
+        funding: Money,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            INSERT INTO amm_pools (opinion_id, owner_id, funding)
+            VALUES ($1,$2,$3)
+        "#,
+            opinion_id,
+            owner_id,
+            funding.get()
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// the maker sold `quantity` shares of `side` for `cost`
+    pub async fn sell<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &str,
+        side: &Side,
+        quantity: Quantity,
+        cost: Money,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        let (yes, no) = match side {
+            Side::Favour => (quantity, Quantity::ZERO),
+            Side::Against => (Quantity::ZERO, quantity),
+        };
+        query!(
+            r#"--sql
+            UPDATE amm_pools
+            SET yes_shares = yes_shares + $2, no_shares = no_shares + $3, collected = collected + $4
+            WHERE opinion_id = $1
+        "#,
+            opinion_id,
+            yes.get(),
+            no.get(),
+            cost.get()
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_one<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &str,
+    ) -> Result<Option<AmmPoolModel>, Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query_as!(
+            AmmPoolModel,
+            r#"--sql
+            SELECT opinion_id, owner_id, funding as "funding: Money", yes_shares as "yes_shares: Quantity", no_shares as "no_shares: Quantity", collected as "collected: Money"
+            FROM amm_pools
+            WHERE opinion_id = $1
+        "#,
+            opinion_id
+        )
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// makers of all unresolved markets
+    pub async fn find_many(&self) -> Result<Vec<AmmPoolModel>, Error> {
+        query_as!(
+            AmmPoolModel,
+            r#"--sql
+            SELECT p.opinion_id, p.owner_id, p.funding as "funding: Money", p.yes_shares as "yes_shares: Quantity", p.no_shares as "no_shares: Quantity", p.collected as "collected: Money"
+            FROM amm_pools p JOIN opinions o ON o.id = p.opinion_id
+            WHERE o.result IS NULL
+        "#
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct AmmPoolModel {
+    pub opinion_id: String,
+    pub owner_id: String,
+    pub funding: Money,
+    pub yes_shares: Quantity,
+    pub no_shares: Quantity,
+    pub collected: Money,
+}
+
+impl AmmPoolModel {
+    /**
+     * what the owner gets back at resolution, the funding and what buyers paid
+     * less the payout of the winning shares the maker sold
+     */
+    pub fn refund(&self, payout: Price, winner: &Side) -> Result<Money, Overflow> {
+        let winning = match winner {
+            Side::Favour => self.yes_shares,
+            Side::Against => self.no_shares,
+        };
+        self.funding
+            .checked_add(self.collected)?
+            .checked_sub(payout.checked_mul(winning)?)
+    }
+}
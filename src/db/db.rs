@@ -2,25 +2,36 @@ use std::env;
 
 use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
 
-use super::{opinion::Opinion, trade::Trade, user::User};
+use super::{
+    amm::Amm, journal::Journal, opinion::Opinion, order::Order, position::Position, trade::Trade,
+    user::User,
+};
 
 #[derive(Clone)]
 pub struct DB {
     pub user: User,
     pub opinion: Opinion,
     pub trade: Trade,
+    pub order: Order,
+    pub position: Position,
+    pub journal: Journal,
+    pub amm: Amm,
     pub pool: Pool<Postgres>,
 }
 
 impl DB {
     pub async fn new() -> Self {
         let db_url = env::var("DATABASE_URL").unwrap();
-        println!("DB URL : {}", db_url);
+        eprintln!("DB URL : {}", db_url);
         let pool = PgPoolOptions::new().connect(&db_url).await.unwrap();
         Self {
             user: User::new(pool.clone()),
             opinion: Opinion::new(pool.clone()),
             trade: Trade::new(pool.clone()),
+            order: Order::new(pool.clone()),
+            position: Position::new(pool.clone()),
+            journal: Journal::new(pool.clone()),
+            amm: Amm::new(pool.clone()),
             pool: pool.clone(),
         }
     }
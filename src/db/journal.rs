@@ -0,0 +1,116 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, Executor, PgPool, Postgres, prelude::FromRow, query, query_as};
+
+#[derive(Clone)]
+pub struct Journal {
+    pool: PgPool,
+}
+
+impl Journal {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /**
+     * adds an entry after the last one of the market and returns its sequence number
+     * only the market task writes the journal of its market, so the next number can't be taken in between
+     */
+    pub async fn append<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &str,
+        event: &str,
+        payload: &str,
+    ) -> Result<i64, Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        let row = query!(
+            r#"--sql
+            INSERT INTO journal (opinion_id, sequence, event, payload)
+            SELECT $1::text, COALESCE(MAX(sequence), 0) + 1, $2, $3
+            FROM journal
+            WHERE opinion_id = $1::text
+            RETURNING sequence
+        "#,
+            opinion_id,
+            event,
+            payload
+        )
+        .fetch_one(executor)
+        .await?;
+        Ok(row.sequence)
+    }
+
+    /// sequence number of the last entry of the market, zero for a market without any
+    pub async fn last_sequence(&self, opinion_id: &str) -> Result<i64, Error> {
+        let row = query!(
+            r#"--sql
+            SELECT COALESCE(MAX(sequence), 0) AS "sequence!"
+            FROM journal
+            WHERE opinion_id = $1
+        "#,
+            opinion_id
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.sequence)
+    }
+
+    /**
+     * entries of every market written after its snapshot, `opinion_ids` and `sequences` pair up,
+     * a market that isn't in them gets all of its entries
+     */
+    pub async fn find_after(
+        &self,
+        opinion_ids: &[String],
+        sequences: &[i64],
+    ) -> Result<Vec<JournalEntryModel>, Error> {
+        query_as!(
+            JournalEntryModel,
+            r#"--sql
+            SELECT j.opinion_id, j.sequence, j.event, j.payload, j.created_at
+            FROM journal j
+            LEFT JOIN UNNEST($1::text[], $2::bigint[]) AS s(opinion_id, sequence) ON s.opinion_id = j.opinion_id
+            WHERE j.sequence > COALESCE(s.sequence, 0)
+            ORDER BY j.opinion_id, j.sequence
+        "#,
+            opinion_ids,
+            sequences
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// entries of one market or of all of them, up to and including `to`, in the order they were written
+    pub async fn find_many(
+        &self,
+        opinion_id: Option<&str>,
+        to: Option<i64>,
+    ) -> Result<Vec<JournalEntryModel>, Error> {
+        query_as!(
+            JournalEntryModel,
+            r#"--sql
+            SELECT opinion_id, sequence, event, payload, created_at
+            FROM journal
+            WHERE ($1::text IS NULL OR opinion_id = $1) AND ($2::bigint IS NULL OR sequence <= $2)
+            ORDER BY opinion_id, sequence
+        "#,
+            opinion_id,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntryModel {
+    pub opinion_id: String,
+    pub sequence: i64,
+    pub event: String,
+    pub payload: String,
+    pub created_at: DateTime<Utc>,
+}
@@ -1,4 +1,9 @@
+#[allow(clippy::module_inception)]
 pub mod db;
 pub mod user;
 pub mod  opinion;
-pub mod trade;
\ No newline at end of file
+pub mod trade;
+pub mod order;
+pub mod position;
+pub mod journal;
+pub mod amm;
\ No newline at end of file
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use sqlx::{Error, Executor, PgPool, Postgres, prelude::FromRow, query, query_as};
 
+use crate::money::{Money, Price, Quantity};
+
 #[derive(Clone)]
 pub struct Opinion {
     pool: PgPool,
@@ -14,24 +16,44 @@ impl Opinion {
     pub async fn find_one(&self, id: String) -> Result<OpinionModel, Error> {
         query_as!(
             OpinionModel,
-            "--sql
-        Select * from opinions where id=$1",
+            r#"--sql
+        SELECT id, question, description, result, payout as "payout: Price", tick_size as "tick_size: Price", min_price as "min_price: Price", max_price as "max_price: Price", max_order_size as "max_order_size: Quantity", max_open_orders, max_position as "max_position: Quantity", max_notional as "max_notional: Money", trading_mode, liquidity as "liquidity: Quantity"
+        FROM opinions WHERE id=$1"#,
             id
         )
         .fetch_one(&self.pool)
         .await
     }
 
-    pub async fn insert(&self, question: String) -> Result<OpinionModel, Error> {
+    /// `id` and `result` of the model are ignored, a new market gets a fresh id and no result
+    pub async fn insert<'a, E>(
+        &self,
+        executor: E,
+        opinion: &OpinionModel,
+    ) -> Result<OpinionModel, Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
         query_as!(
             OpinionModel,
-            "--sql
-        INSERT INTO opinions (question)
-        VALUES ($1)
-        RETURNING id, question, description, result",
-            question
+            r#"--sql
+        INSERT INTO opinions (question, description, payout, tick_size, min_price, max_price, max_order_size, max_open_orders, max_position, max_notional, trading_mode, liquidity)
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12)
+        RETURNING id, question, description, result, payout as "payout: Price", tick_size as "tick_size: Price", min_price as "min_price: Price", max_price as "max_price: Price", max_order_size as "max_order_size: Quantity", max_open_orders, max_position as "max_position: Quantity", max_notional as "max_notional: Money", trading_mode, liquidity as "liquidity: Quantity""#,
+            opinion.question,
+            opinion.description,
+            opinion.payout.get(),
+            opinion.tick_size.get(),
+            opinion.min_price.get(),
+            opinion.max_price.get(),
+            opinion.max_order_size.map(Quantity::get),
+            opinion.max_open_orders,
+            opinion.max_position.map(Quantity::get),
+            opinion.max_notional.map(Money::get),
+            opinion.trading_mode,
+            opinion.liquidity.map(Quantity::get)
         )
-        .fetch_one(&self.pool)
+        .fetch_one(executor)
         .await
     }
 
@@ -39,40 +61,57 @@ impl Opinion {
         query_as!(
             OpinionModel,
             r#"--sql 
-        SELECT * FROM opinions WHERE result is NULL"#
+        SELECT id, question, description, result, payout as "payout: Price", tick_size as "tick_size: Price", min_price as "min_price: Price", max_price as "max_price: Price", max_order_size as "max_order_size: Quantity", max_open_orders, max_position as "max_position: Quantity", max_notional as "max_notional: Money", trading_mode, liquidity as "liquidity: Quantity"
+        FROM opinions WHERE result is NULL"#
         )
         .fetch_all(&self.pool)
         .await
     }
 
+    /// sets the result of a market that has none yet, false if it already had one and nothing changed
     pub async fn update_result<'a, E>(
         &self,
         executor: E,
         opinion_id: &String,
         result: bool,
-    ) -> Result<(), Error>
+    ) -> Result<bool, Error>
     where
         E: Executor<'a, Database = Postgres>,
     {
-        query!(
+        let updated = query!(
             r#"--sql
             UPDATE opinions
             SET result=$1
-            WHERE id=$2
+            WHERE id=$2 AND result IS NULL
         "#,
             result,
             opinion_id
         )
         .execute(executor)
         .await?;
-        Ok(())
+        Ok(updated.rows_affected() > 0)
     }
 }
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
+#[serde(rename_all = "camelCase")]
 pub struct OpinionModel {
     pub id: Option<String>,
     pub question: String,
     pub description: Option<String>,
     pub result: Option<bool>,
+    /// what one winning share pays out at resolution
+    pub payout: Price,
+    /// prices have to be a multiple of it
+    pub tick_size: Price,
+    pub min_price: Price,
+    pub max_price: Price,
+    /// risk limits of the market, `None` if it has none
+    pub max_order_size: Option<Quantity>,
+    pub max_open_orders: Option<i64>,
+    pub max_position: Option<Quantity>,
+    pub max_notional: Option<Money>,
+    /// `order_book` or `amm`, only a market in `amm` mode has the liquidity of its maker
+    pub trading_mode: String,
+    pub liquidity: Option<Quantity>,
 }
@@ -0,0 +1,511 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, Executor, PgPool, Postgres, prelude::FromRow, query, query_as};
+
+use crate::{
+    conditional::Trigger,
+    money::{Price, Quantity},
+};
+
+#[derive(Clone)]
+pub struct Order {
+    pool: PgPool,
+}
+
+impl Order {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn create<'a, E>(&self, executor: E, order: &OrderModel) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+        INSERT INTO orders (id, opinion_id, user_id, side, price, quantity, remaining_quantity, status, order_type, time_in_force, expires_at, action, client_order_id, display_quantity)
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14)
+        "#,
+            &order.id,
+            &order.opinion_id,
+            &order.user_id,
+            &order.side,
+            order.price.get(),
+            order.quantity.get(),
+            order.remaining_quantity.get(),
+            &order.status,
+            &order.order_type,
+            &order.time_in_force,
+            order.expires_at,
+            &order.action,
+            order.client_order_id,
+            order.display_quantity.map(Quantity::get)
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// the trigger of a conditional order, stored next to the pending order
+    pub async fn create_conditional<'a, E>(
+        &self,
+        executor: E,
+        order_id: &str,
+        trigger: &Trigger,
+        self_trade_prevention: &str,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            INSERT INTO conditional_orders (order_id, trigger_price, trigger_direction, self_trade_prevention)
+            VALUES ($1,$2,$3,$4)
+        "#,
+            order_id,
+            trigger.price.get(),
+            trigger.direction.as_str(),
+            self_trade_prevention
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// a pending order whose trigger fired becomes open, it queues behind everything already in the book
+    pub async fn activate<'a, E>(&self, executor: E, order_id: &String) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            WITH triggered AS (
+                UPDATE conditional_orders SET triggered_at = CURRENT_TIMESTAMP WHERE order_id = $1
+            )
+            UPDATE orders
+            SET status = 'open',
+                priority = nextval('order_priority_seq'),
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1 AND status = 'pending'
+        "#,
+            order_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// takes `quantity` off the open part of an order, it becomes filled once nothing is left
+    pub async fn fill<'a, E>(
+        &self,
+        executor: E,
+        order_id: &String,
+        quantity: Quantity,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            UPDATE orders
+            SET remaining_quantity = remaining_quantity - $2,
+                status = CASE WHEN remaining_quantity - $2 = 0 THEN 'filled' ELSE 'partially_filled' END,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+            order_id,
+            quantity.get()
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// cancels part of the open quantity, the order stays in the book with the rest
+    pub async fn decrement<'a, E>(
+        &self,
+        executor: E,
+        order_id: &String,
+        quantity: Quantity,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            UPDATE orders
+            SET quantity = quantity - $2,
+                remaining_quantity = remaining_quantity - $2,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+            order_id,
+            quantity.get()
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn cancel<'a, E>(&self, executor: E, order_id: &String) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            UPDATE orders
+            SET status = 'cancelled', updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+            order_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn expire<'a, E>(&self, executor: E, order_id: &String) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            UPDATE orders
+            SET status = 'expired', updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+            order_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// cancels everything still resting or waiting for a trigger in a market, used when the market gets resolved
+    pub async fn cancel_resting_by_opinion_id<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &String,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            UPDATE orders
+            SET status = 'cancelled', updated_at = CURRENT_TIMESTAMP
+            WHERE opinion_id = $1 AND status IN ('pending', 'open', 'partially_filled')
+        "#,
+            opinion_id
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /**
+     * sets a new price and open quantity, the original quantity moves by the same amount as the open one
+     * `requeue` gives the order a fresh priority, as it lost its place in the queue
+     */
+    pub async fn amend<'a, E>(
+        &self,
+        executor: E,
+        order_id: &String,
+        price: Price,
+        remaining_quantity: Quantity,
+        requeue: bool,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            UPDATE orders
+            SET price = $2,
+                quantity = quantity - remaining_quantity + $3,
+                remaining_quantity = $3,
+                priority = CASE WHEN $4 THEN nextval('order_priority_seq') ELSE priority END,
+                updated_at = CURRENT_TIMESTAMP
+            WHERE id = $1
+        "#,
+            order_id,
+            price.get(),
+            remaining_quantity.get(),
+            requeue
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// orders of the user that still rest in a book, oldest first
+    pub async fn find_open_by_user_id(&self, user_id: &String) -> Result<Vec<OrderModel>, Error> {
+        query_as!(
+            OrderModel,
+            r#"--sql
+            SELECT id, opinion_id, user_id, side, price as "price: Price", quantity as "quantity: Quantity", remaining_quantity as "remaining_quantity: Quantity", status, order_type, time_in_force, expires_at, action, client_order_id, display_quantity as "display_quantity: Quantity", created_at, updated_at
+            FROM orders
+            WHERE user_id = $1 AND status IN ('open', 'partially_filled')
+            ORDER BY created_at
+        "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn find_by_client_order_id(
+        &self,
+        user_id: &String,
+        client_order_id: &str,
+    ) -> Result<Option<ClientOrderModel>, Error> {
+        query_as!(
+            ClientOrderModel,
+            r#"--sql
+            SELECT id, opinion_id, client_order_id as "client_order_id!", status, response_code, response
+            FROM orders
+            WHERE user_id = $1 AND client_order_id = $2
+        "#,
+            user_id,
+            client_order_id
+        )
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// client ids out of `client_order_ids` that the user already gave to an order
+    pub async fn find_used_client_order_ids(
+        &self,
+        user_id: &String,
+        client_order_ids: &[String],
+    ) -> Result<Vec<String>, Error> {
+        let rows = query!(
+            r#"--sql
+            SELECT client_order_id as "client_order_id!"
+            FROM orders
+            WHERE user_id = $1 AND client_order_id = ANY($2)
+        "#,
+            user_id,
+            client_order_ids
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|row| row.client_order_id).collect())
+    }
+
+    /// keeps the response of the request that placed the order, a retry of the request gets it again
+    pub async fn save_response(
+        &self,
+        order_id: &String,
+        response_code: i16,
+        response: &str,
+    ) -> Result<(), Error> {
+        query!(
+            r#"--sql
+            UPDATE orders SET response_code = $2, response = $3 WHERE id = $1
+        "#,
+            order_id,
+            response_code,
+            response
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /**
+     * every order the user sent, newest first, with the trades it took part in
+     * `opinion_id`, `client_order_id` and `status` narrow it down to one market, order or status,
+     * `from` and `to` to orders sent in that range
+     */
+    pub async fn find_history(
+        &self,
+        user_id: &String,
+        opinion_id: Option<&str>,
+        client_order_id: Option<&str>,
+        status: Option<&str>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<OrderHistoryModel>, Error> {
+        query_as!(
+            OrderHistoryModel,
+            r#"--sql
+            SELECT o.id, o.client_order_id, o.opinion_id, o.side, o.action, o.order_type, o.time_in_force,
+                o.price as "price: Price", o.quantity as "quantity: Quantity", o.remaining_quantity as "remaining_quantity: Quantity",
+                (o.quantity - o.remaining_quantity) as "filled_quantity!: Quantity",
+                o.display_quantity as "display_quantity: Quantity",
+                SUM(CASE WHEN o.side = 'favour' THEN t.favour_price ELSE t.against_price END * t.quantity)::float8
+                    / NULLIF(SUM(t.quantity), 0) as average_fill_price,
+                o.status,
+                COALESCE(array_agg(t.id ORDER BY t.created_at) FILTER (WHERE t.id IS NOT NULL), '{}') as "trade_ids!",
+                o.expires_at, o.created_at, o.updated_at
+            FROM orders o
+            LEFT JOIN trades t ON t.favour_order_id = o.id OR t.against_order_id = o.id
+            WHERE o.user_id = $1
+              AND ($2::text IS NULL OR o.opinion_id = $2)
+              AND ($3::text IS NULL OR o.client_order_id = $3)
+              AND ($4::text IS NULL OR o.status = $4)
+              AND ($5::timestamptz IS NULL OR o.created_at >= $5)
+              AND ($6::timestamptz IS NULL OR o.created_at < $6)
+            GROUP BY o.id
+            ORDER BY o.created_at DESC
+        "#,
+            user_id,
+            opinion_id,
+            client_order_id,
+            status,
+            from,
+            to
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// conditional orders waiting for their trigger, oldest first, of one user and/or one market if given
+    pub async fn find_pending_conditionals(
+        &self,
+        user_id: Option<&str>,
+        opinion_id: Option<&str>,
+    ) -> Result<Vec<ConditionalOrderModel>, Error> {
+        query_as!(
+            ConditionalOrderModel,
+            r#"--sql
+            SELECT o.id, o.client_order_id, o.opinion_id, o.user_id, o.side, o.action, o.order_type, o.time_in_force,
+                o.price as "price: Price", o.quantity as "quantity: Quantity",
+                c.trigger_price as "trigger_price: Price", c.trigger_direction, c.self_trade_prevention, o.created_at
+            FROM orders o
+            JOIN conditional_orders c ON c.order_id = o.id
+            WHERE o.status = 'pending'
+              AND ($1::text IS NULL OR o.user_id = $1)
+              AND ($2::text IS NULL OR o.opinion_id = $2)
+            ORDER BY o.priority
+        "#,
+            user_id,
+            opinion_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// orders still resting in the book, oldest priority first so they can be queued again in the same order
+    pub async fn find_resting(&self) -> Result<Vec<OrderModel>, Error> {
+        query_as!(
+            OrderModel,
+            r#"--sql
+            SELECT id, opinion_id, user_id, side, price as "price: Price", quantity as "quantity: Quantity", remaining_quantity as "remaining_quantity: Quantity", status, order_type, time_in_force, expires_at, action, client_order_id, display_quantity as "display_quantity: Quantity", created_at, updated_at
+            FROM orders
+            WHERE status IN ('open', 'partially_filled')
+            ORDER BY priority
+        "#
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+/// an order as the user sees it in their history, with what came of it
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderHistoryModel {
+    pub id: String,
+    pub client_order_id: Option<String>,
+    pub opinion_id: String,
+    pub side: String,
+    pub action: String,
+    pub order_type: String,
+    pub time_in_force: String,
+    /// limit price, the edge of the price band for market orders
+    pub price: Price,
+    pub quantity: Quantity,
+    pub remaining_quantity: Quantity,
+    pub filled_quantity: Quantity,
+    /// size of the slices of an iceberg order
+    pub display_quantity: Option<Quantity>,
+    /// price of the outcome the order bought or sold, weighted by quantity, `None` if nothing was filled
+    pub average_fill_price: Option<f64>,
+    pub status: String,
+    pub trade_ids: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct OrderModel {
+    pub id: String,
+    pub opinion_id: String,
+    pub user_id: String,
+    pub side: String,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub remaining_quantity: Quantity,
+    pub status: String,
+    pub order_type: String,
+    pub time_in_force: String,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub action: String,
+    /// id the user gave the order, unique among their orders
+    pub client_order_id: Option<String>,
+    /// size of the slices of an iceberg order
+    pub display_quantity: Option<Quantity>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// a pending conditional order with its trigger
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct ConditionalOrderModel {
+    pub id: String,
+    pub client_order_id: Option<String>,
+    pub opinion_id: String,
+    pub user_id: String,
+    pub side: String,
+    pub action: String,
+    pub order_type: String,
+    pub time_in_force: String,
+    pub price: Price,
+    pub quantity: Quantity,
+    pub trigger_price: Price,
+    pub trigger_direction: String,
+    pub self_trade_prevention: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// an order found by the id the user gave it, with the response its request got if that is known yet
+#[derive(Debug, Clone)]
+pub struct ClientOrderModel {
+    pub id: String,
+    pub opinion_id: String,
+    pub client_order_id: String,
+    pub status: String,
+    pub response_code: Option<i16>,
+    /// the JSON body that was sent back
+    pub response: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    /// a conditional order waiting for its trigger, not in the book yet
+    Pending,
+    Open,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Expired,
+}
+
+impl OrderStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Open => "open",
+            OrderStatus::PartiallyFilled => "partially_filled",
+            OrderStatus::Filled => "filled",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Expired => "expired",
+        }
+    }
+}
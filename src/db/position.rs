@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, Executor, PgPool, Postgres, prelude::FromRow, query, query_as};
+
+use crate::money::{Money, Quantity};
+
+#[derive(Clone)]
+pub struct Position {
+    pool: PgPool,
+}
+
+impl Position {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /**
+     * adds bought shares to a position, `locked` is the part of the hold that backs them
+     * and `cost` what they were bought for
+     */
+    #[allow(clippy::too_many_arguments)]
+    pub async fn open<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &str,
+        user_id: &str,
+        side: &str,
+        quantity: Quantity,
+        locked: Money,
+        cost: Money,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            INSERT INTO positions (opinion_id, user_id, side, quantity, locked, cost)
+            VALUES ($1,$2,$3,$4,$5,$6)
+            ON CONFLICT (opinion_id, user_id, side) DO UPDATE
+            SET quantity = positions.quantity + EXCLUDED.quantity,
+                locked = positions.locked + EXCLUDED.locked,
+                cost = positions.cost + EXCLUDED.cost
+        "#,
+            opinion_id,
+            user_id,
+            side,
+            quantity.get(),
+            locked.get(),
+            cost.get()
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /// promises shares to a sell order, fails with `RowNotFound` if the user doesn't have that many free
+    pub async fn reserve<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &str,
+        user_id: &str,
+        side: &str,
+        quantity: Quantity,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        let result = query!(
+            r#"--sql
+            UPDATE positions
+            SET reserved_quantity = reserved_quantity + $4
+            WHERE opinion_id = $1 AND user_id = $2 AND side = $3
+              AND quantity - reserved_quantity >= $4
+        "#,
+            opinion_id,
+            user_id,
+            side,
+            quantity.get()
+        )
+        .execute(executor)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+        Ok(())
+    }
+
+    pub async fn unreserve<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &str,
+        user_id: &str,
+        side: &str,
+        quantity: Quantity,
+    ) -> Result<(), Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query!(
+            r#"--sql
+            UPDATE positions
+            SET reserved_quantity = reserved_quantity - $4
+            WHERE opinion_id = $1 AND user_id = $2 AND side = $3
+        "#,
+            opinion_id,
+            user_id,
+            side,
+            quantity.get()
+        )
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    /**
+     * takes sold shares out of a position, they were reserved by the sell order
+     * returns the part of the locked collateral that belonged to them, the rest stays with the remaining shares
+     * the cost goes down by the same share
+     */
+    pub async fn close<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &str,
+        user_id: &str,
+        side: &str,
+        quantity: Quantity,
+    ) -> Result<Money, Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        let row = query!(
+            r#"--sql
+            WITH old AS (
+                SELECT quantity, locked, cost FROM positions
+                WHERE opinion_id = $1 AND user_id = $2 AND side = $3
+                FOR UPDATE
+            )
+            UPDATE positions p
+            SET quantity = p.quantity - $4,
+                reserved_quantity = p.reserved_quantity - $4,
+                locked = p.locked - old.locked * $4 / old.quantity,
+                cost = p.cost - old.cost * $4 / old.quantity
+            FROM old
+            WHERE p.opinion_id = $1 AND p.user_id = $2 AND p.side = $3
+            RETURNING old.locked * $4 / old.quantity AS "released!: Money"
+        "#,
+            opinion_id,
+            user_id,
+            side,
+            quantity.get()
+        )
+        .fetch_one(executor)
+        .await?;
+        Ok(row.released)
+    }
+
+    pub async fn find_by_opinion_id<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &str,
+    ) -> Result<Vec<PositionModel>, Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query_as!(
+            PositionModel,
+            r#"--sql
+            SELECT opinion_id, user_id, side, quantity as "quantity: Quantity", reserved_quantity as "reserved_quantity: Quantity", locked as "locked: Money", cost as "cost: Money"
+            FROM positions
+            WHERE opinion_id = $1 AND quantity > 0
+        "#,
+            opinion_id
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    pub async fn find_by_opinion_and_user_id<'a, E>(
+        &self,
+        executor: E,
+        opinion_id: &str,
+        user_id: &str,
+    ) -> Result<Vec<PositionModel>, Error>
+    where
+        E: Executor<'a, Database = Postgres>,
+    {
+        query_as!(
+            PositionModel,
+            r#"--sql
+            SELECT opinion_id, user_id, side, quantity as "quantity: Quantity", reserved_quantity as "reserved_quantity: Quantity", locked as "locked: Money", cost as "cost: Money"
+            FROM positions
+            WHERE opinion_id = $1 AND user_id = $2
+        "#,
+            opinion_id,
+            user_id
+        )
+        .fetch_all(executor)
+        .await
+    }
+
+    /// shares the user still holds in markets that are not resolved yet
+    pub async fn find_open_by_user_id(&self, user_id: &str) -> Result<Vec<PositionModel>, Error> {
+        query_as!(
+            PositionModel,
+            r#"--sql
+            SELECT p.opinion_id, p.user_id, p.side, p.quantity as "quantity: Quantity", p.reserved_quantity as "reserved_quantity: Quantity", p.locked as "locked: Money", p.cost as "cost: Money"
+            FROM positions p JOIN opinions o ON p.opinion_id = o.id
+            WHERE p.user_id = $1 AND p.quantity > 0 AND o.result IS NULL
+        "#,
+            user_id
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, FromRow)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionModel {
+    pub opinion_id: String,
+    pub user_id: String,
+    pub side: String,
+    pub quantity: Quantity,
+    pub reserved_quantity: Quantity,
+    pub locked: Money,
+    /// what the shares were bought for, the sold ones taken out at their average
+    pub cost: Money,
+}
@@ -1,6 +1,10 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
 use sqlx::{Executor, PgPool, Postgres, prelude::FromRow, query};
 
+use crate::money::{Price, Quantity};
+
 #[derive(Clone)]
 pub struct Trade {
     pool: PgPool,
@@ -17,12 +21,17 @@ impl Trade {
     {
         query!(
             r#"--sql
-        INSERT INTO trades (opinion_id, favour_user_id,against_user_id, favour_price, against_price,quantity )
-        VALUES ($1,$2,$3,$4,$5,$6)
+        INSERT INTO trades (opinion_id, favour_user_id,against_user_id, favour_price, against_price,quantity, favour_action, against_action, favour_order_id, against_order_id, taker_side )
+        VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11)
         "#,
             &trade.opinion_id,
             &trade.favour_user_id,
-            &trade.against_user_id,trade.favour_price as i64,trade.against_price as i64,trade.quantity as i64
+            &trade.against_user_id,trade.favour_price.get(),trade.against_price.get(),trade.quantity.get(),
+            &trade.favour_action,
+            &trade.against_action,
+            trade.favour_order_id,
+            trade.against_order_id,
+            trade.taker_side
         )
         .execute(executor)
         .await?;
@@ -30,6 +39,23 @@ impl Trade {
         Ok(())
     }
 
+    /// YES price of the last trade booked in every market that traded
+    pub async fn find_last_prices(&self) -> Result<HashMap<String, Price>, sqlx::Error> {
+        let rows = query!(
+            r#"--sql
+            SELECT DISTINCT ON (opinion_id) opinion_id, favour_price
+            FROM trades
+            ORDER BY opinion_id, seq DESC
+        "#
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.opinion_id, row.favour_price.into()))
+            .collect())
+    }
+
     pub async fn get_trades(
         &self,
         user_id: Option<String>,
@@ -44,7 +70,12 @@ impl Trade {
             favour_user_id, 
             against_user_id, 
             favour_price, 
-            against_price, quantity
+            against_price, quantity,
+            favour_action,
+            against_action,
+            favour_order_id,
+            against_order_id,
+            taker_side
         FROM trades t JOIN opinions o ON t.opinion_id = o.id WHERE ($1::text IS NULL OR favour_user_id=$1 OR against_user_id=$1) AND (($2::bool = true AND o.result IS NULL) OR
         ($2::bool = false AND o.result IS NOT NULL))
         "#,
@@ -61,45 +92,14 @@ impl Trade {
                 opinion_id: row.opinion_id,
                 favour_user_id: row.favour_user_id,
                 against_user_id: row.against_user_id,
-                favour_price: row.favour_price.try_into().unwrap(), // i16 -> u16
-                against_price: row.against_price.try_into().unwrap(), // i16 -> u16
-                quantity: row.quantity.try_into().unwrap(),
-            })
-            .collect();
-
-        Ok(t)
-    }
-
-    pub async fn get_trades_by_opinion_id(
-        &self,
-        opinion_id: &String,
-    ) -> Result<Vec<TradeModel>, sqlx::Error> {
-        let trades = query!(
-            r#"--sql
-            SELECT  id, 
-            opinion_id, 
-            favour_user_id, 
-            against_user_id, 
-            favour_price, 
-            against_price, quantity
-        FROM trades
-        WHERE opinion_id = $1
-        "#,
-            opinion_id
-        )
-        .fetch_all(&self.pool)
-        .await?;
-
-        let t = trades
-            .into_iter()
-            .map(|row| TradeModel {
-                id: Some(row.id),
-                opinion_id: row.opinion_id,
-                favour_user_id: row.favour_user_id,
-                against_user_id: row.against_user_id,
-                favour_price: row.favour_price.try_into().unwrap(), // i16 -> u16
-                against_price: row.against_price.try_into().unwrap(), // i16 -> u16
-                quantity: row.quantity.try_into().unwrap(),
+                favour_price: row.favour_price.into(),
+                against_price: row.against_price.into(),
+                quantity: row.quantity.into(),
+                favour_action: row.favour_action,
+                against_action: row.against_action,
+                favour_order_id: row.favour_order_id,
+                against_order_id: row.against_order_id,
+                taker_side: row.taker_side,
             })
             .collect();
 
@@ -114,9 +114,18 @@ pub struct TradeModel {
     pub opinion_id: String,
     pub favour_user_id: String,
     pub against_user_id: String,
-    pub favour_price: u16,
-    pub against_price: u16,
-    pub quantity: u16,
+    pub favour_price: Price,
+    pub against_price: Price,
+    pub quantity: Quantity,
+    // favour user either bought YES or sold NO, against user bought NO or sold YES
+    pub favour_action: String,
+    pub against_action: String,
+    // orders on both sides, none for trades from before orders were stored
+    pub favour_order_id: Option<String>,
+    pub against_order_id: Option<String>,
+    // favour or against, whichever order came in and traded with the resting one, that one is the maker
+    // none for trades from before it was recorded
+    pub taker_side: Option<String>,
 }
 
 impl TradeModel {
@@ -125,9 +134,9 @@ impl TradeModel {
         opinion_id: String,
         favour_user_id: String,
         against_user_id: String,
-        favour_price: u16,
-        against_price: u16,
-        quantity: u16,
+        favour_price: Price,
+        against_price: Price,
+        quantity: Quantity,
     ) -> Self {
         Self {
             id,
@@ -137,6 +146,11 @@ impl TradeModel {
             favour_price,
             against_price,
             quantity,
+            favour_action: "buy".to_string(),
+            against_action: "buy".to_string(),
+            favour_order_id: None,
+            against_order_id: None,
+            taker_side: None,
         }
     }
 }
@@ -1,8 +1,10 @@
-use chrono::NaiveDateTime;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::{PgPool, query, query_as};
 use uuid::Uuid;
 
+use crate::money::{Money, Quantity};
+
 #[derive(Clone)]
 pub struct User {
     pool: PgPool,
@@ -16,8 +18,13 @@ pub struct UserModel {
     pub password: String,
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>,
-    pub balance: i32,
-    pub hold_balance: i32,
+    pub balance: Money,
+    pub hold_balance: Money,
+    // account default, an order can choose its own mode
+    #[serde(default)]
+    pub self_trade_prevention: String,
+    #[serde(default)]
+    pub is_admin: bool,
 }
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 
@@ -30,10 +37,20 @@ pub struct CreateUserDto {
 #[derive(Debug, Serialize, Deserialize, sqlx::FromRow)]
 pub struct UserTransactionsModel {
     pub id: Uuid,
-    pub created_at: NaiveDateTime,
+    pub created_at: DateTime<Utc>,
     pub user_id: String,
-    pub old_balance: i32,
-    pub new_balance: i32,
+    pub old_balance: Money,
+    pub new_balance: Money,
+}
+
+/// risk limits set on the account, `None` if there is none
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct UserLimitsModel {
+    pub max_order_size: Option<Quantity>,
+    pub max_open_orders: Option<i64>,
+    pub max_position: Option<Quantity>,
+    pub max_notional: Option<Money>,
 }
 
 impl User {
@@ -47,7 +64,7 @@ impl User {
             r#"--sql
         INSERT INTO users (name, email, password)
         VALUES ($1,$2,$3)
-        RETURNING id, name, email, password, created_at, updated_at, balance, hold_balance
+        RETURNING id, name, email, password, created_at, updated_at, balance as "balance: Money", hold_balance as "hold_balance: Money", self_trade_prevention, is_admin
         "#,
             user.name,
             user.email,
@@ -61,7 +78,7 @@ impl User {
         query_as!(
             UserModel,
             r#"--sql 
-        SELECT * from users"#
+        SELECT id, name, email, password, created_at, updated_at, balance as "balance: Money", hold_balance as "hold_balance: Money", self_trade_prevention, is_admin from users"#
         )
         .fetch_all(&self.pool)
         .await
@@ -74,7 +91,7 @@ impl User {
         query_as!(
             UserTransactionsModel,
             r#"--sql
-        SELECT * FROM user_balance_logs WHERE user_id=$1
+        SELECT id, created_at, user_id, old_balance as "old_balance: Money", new_balance as "new_balance: Money" FROM user_balance_logs WHERE user_id=$1
         ORDER BY created_at DESC
         "#,
             id
@@ -87,7 +104,7 @@ impl User {
         query_as!(
             UserModel,
             r#"--sql
-        SELECT id, name, email, password, created_at, updated_at, balance, hold_balance FROM users WHERE id=$1
+        SELECT id, name, email, password, created_at, updated_at, balance as "balance: Money", hold_balance as "hold_balance: Money", self_trade_prevention, is_admin FROM users WHERE id=$1
         "#,
             &id
         )
@@ -95,15 +112,23 @@ impl User {
         .await
     }
 
-    pub async fn hold_balance(&self, id: &String, amount: u16) -> Result<(), sqlx::Error> {
+    pub async fn hold_balance<'a, E>(
+        &self,
+        executor: E,
+        id: &String,
+        amount: Money,
+    ) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
         query!(
             r#"--sql
             UPDATE users set hold_balance=hold_balance+$1 , balance=balance-$1  where id=$2
                 "#,
-            amount as i32,
+            amount.get(),
             id
         )
-        .execute(&self.pool)
+        .execute(executor)
         .await?;
         Ok(())
     }
@@ -112,7 +137,7 @@ impl User {
         &self,
         executor: E,
         user_id: &String,
-        amount: u16,
+        amount: Money,
     ) -> Result<(), sqlx::Error>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
@@ -121,7 +146,7 @@ impl User {
             r#"--sql
         UPDATE users set hold_balance=hold_balance-$1 , balance=balance+$1  where id=$2
         "#,
-            amount as i32,
+            amount.get(),
             user_id
         )
         .execute(executor)
@@ -129,47 +154,108 @@ impl User {
         Ok(())
     }
 
-    pub async fn update_balance_post_result<'a, E>(
+    /**
+     * takes `hold` out of the held balance for good and pays `payout` into the balance
+     * used when bought shares are paid for, sold or resolved
+     */
+    pub async fn settle_hold<'a, E>(
         &self,
         executor: E,
-        winner_id: &String,
-        loser_id: &String,
-        winner_hold: u16,
-        loser_hold: u16,
-        winning_price: u16,
+        user_id: &str,
+        hold: Money,
+        payout: Money,
     ) -> Result<(), sqlx::Error>
     where
         E: sqlx::Executor<'a, Database = sqlx::Postgres>,
     {
         query!(
             r#"--sql
-            UPDATE users
-            SET
-              balance = balance + CASE
-                WHEN id = $1 THEN $2
-                ELSE 0
-              END,
-              hold_balance = hold_balance - CASE
-                WHEN id = $1 AND $1 = $4 THEN ($3::numeric + $5::numeric)
-                WHEN id = $1 THEN $3
-                WHEN id = $4 THEN $5
-                ELSE 0
-              END
-            WHERE id IN ($1, $4);"#,
-            winner_id,
-            winning_price as i32,
-            winner_hold as i32,
-            loser_id,
-            loser_hold as i32
+            UPDATE users set hold_balance=hold_balance-$1 , balance=balance+$2  where id=$3
+            "#,
+            hold.get(),
+            payout.get(),
+            user_id
         )
         .execute(executor)
         .await?;
         Ok(())
     }
 
+    pub async fn update_self_trade_prevention(
+        &self,
+        id: &String,
+        mode: &str,
+    ) -> Result<(), sqlx::Error> {
+        query!(
+            r#"--sql
+            UPDATE users SET self_trade_prevention=$1, updated_at=CURRENT_TIMESTAMP WHERE id=$2
+            "#,
+            mode,
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// read from the table and not the token, so taking the role away counts right away
+    pub async fn is_admin(&self, id: &String) -> Result<bool, sqlx::Error> {
+        let admin = query!(
+            r#"--sql
+            SELECT is_admin FROM users WHERE id=$1
+            "#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(admin.is_some_and(|admin| admin.is_admin))
+    }
+
+    pub async fn get_limits<'a, E>(
+        &self,
+        executor: E,
+        id: &String,
+    ) -> Result<UserLimitsModel, sqlx::Error>
+    where
+        E: sqlx::Executor<'a, Database = sqlx::Postgres>,
+    {
+        query_as!(
+            UserLimitsModel,
+            r#"--sql
+            SELECT max_order_size as "max_order_size: Quantity", max_open_orders, max_position as "max_position: Quantity", max_notional as "max_notional: Money"
+            FROM users WHERE id=$1
+            "#,
+            id
+        )
+        .fetch_one(executor)
+        .await
+    }
+
+    /// replaces every limit of the account, a `None` removes that limit, returns false if there is no such user
+    pub async fn update_limits(
+        &self,
+        id: &String,
+        limits: &UserLimitsModel,
+    ) -> Result<bool, sqlx::Error> {
+        let updated = query!(
+            r#"--sql
+            UPDATE users SET max_order_size=$1, max_open_orders=$2, max_position=$3, max_notional=$4, updated_at=CURRENT_TIMESTAMP
+            WHERE id=$5
+            "#,
+            limits.max_order_size.map(|size| size.get()),
+            limits.max_open_orders,
+            limits.max_position.map(|size| size.get()),
+            limits.max_notional.map(|amount| amount.get()),
+            id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(updated.rows_affected() > 0)
+    }
+
     pub async fn get_by_email(&self, email: &String) -> Result<UserModel, sqlx::Error> {
         query_as!(UserModel,
         r#"--sql 
-        SELECT id, name, email, password, created_at, updated_at, balance, hold_balance FROM users WHERE email=$1"#,email).fetch_one(&self.pool).await
+        SELECT id, name, email, password, created_at, updated_at, balance as "balance: Money", hold_balance as "hold_balance: Money", self_trade_prevention, is_admin FROM users WHERE email=$1"#,email).fetch_one(&self.pool).await
     }
 }
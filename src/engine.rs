@@ -0,0 +1,408 @@
+//! the matching engine without any IO, it works out what an incoming order does to the book and to the
+//! accounts and hands it back as data, the market task books that and applies it to its own book afterwards
+//! the same book, order and time always give the same execution
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    db::order::OrderStatus,
+    money::{Money, Overflow, Price, Quantity},
+    state::{
+        Action, CreateOrderDto, MarketParams, Matched, Order, OrderBook, PostOnly, Side,
+        TimeInForce,
+    },
+};
+
+/// a trade between the incoming order and a resting one, at the price of the resting order
+#[derive(Debug, Clone)]
+pub struct Fill {
+    /// the resting order as it was before the fill
+    pub maker: Order,
+    pub quantity: Quantity,
+    /// prices of the YES and the NO share, they add up to the payout
+    pub favour_price: Price,
+    pub against_price: Price,
+}
+
+impl Fill {
+    /// price of the outcome `side`
+    pub fn price(&self, side: &Side) -> Price {
+        match side {
+            Side::Favour => self.favour_price,
+            Side::Against => self.against_price,
+        }
+    }
+
+    /// the orders on the YES and on the NO side of the trade, a sell is on the side of the outcome it doesn't sell
+    pub fn parties<'a>(&'a self, taker: &'a Order) -> (&'a Order, &'a Order) {
+        match taker.book_side() {
+            Side::Favour => (taker, &self.maker),
+            Side::Against => (&self.maker, taker),
+        }
+    }
+}
+
+/// what happens to the book once the execution is booked
+#[derive(Debug, Clone)]
+pub enum BookChange {
+    /// a resting order gives up `quantity` to a fill or to self trade prevention, it leaves the book at zero
+    Reduce {
+        side: Side,
+        order_id: String,
+        quantity: Quantity,
+    },
+    /// an amend lowers a resting order to `quantity`, it never loses its place in the queue
+    Amend {
+        side: Side,
+        order_id: String,
+        quantity: Quantity,
+    },
+    /// the unfilled remainder of the incoming order rests
+    Rest(Order),
+}
+
+/**
+ * a change to the money or the shares of one user
+ * buys hold their notional and sells reserve their shares before they reach the engine,
+ * the effects move those holds and reservations on
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum BalanceEffect {
+    /// part of the hold of a buy goes back to the balance
+    Release { user_id: String, amount: Money },
+    /// reserved shares of a sell are free again
+    Unreserve {
+        user_id: String,
+        side: Side,
+        quantity: Quantity,
+    },
+    /// `hold` leaves the held balance for good and `payout` is paid into the balance
+    SettleHold {
+        user_id: String,
+        hold: Money,
+        payout: Money,
+    },
+    /// bought shares, `locked` is the part of the hold that backs them and `cost` what they were bought for
+    OpenPosition {
+        user_id: String,
+        side: Side,
+        quantity: Quantity,
+        locked: Money,
+        cost: Money,
+    },
+    /// sold shares leave the position, the collateral that backed them is released and `proceeds` are paid
+    ClosePosition {
+        user_id: String,
+        side: Side,
+        quantity: Quantity,
+        proceeds: Money,
+    },
+}
+
+/// everything an incoming order does, computed from the book as it is
+#[derive(Debug, Clone)]
+pub struct Execution {
+    /// in the order they were matched
+    pub fills: Vec<Fill>,
+    /// own resting orders hit by self trade prevention with the quantity cancelled off them
+    pub self_trade_cancels: Vec<(Order, Quantity)>,
+    /// quantity of the incoming order that is cancelled, by self trade prevention or because it may not rest
+    pub cancelled: Quantity,
+    pub status: OrderStatus,
+    pub book: Vec<BookChange>,
+    pub effects: Vec<BalanceEffect>,
+}
+
+impl Execution {
+    /// quantity of the incoming order left in the book
+    pub fn resting(&self) -> Quantity {
+        self.book
+            .iter()
+            .find_map(|change| match change {
+                BookChange::Rest(order) => Some(order.quantity),
+                BookChange::Reduce { .. } | BookChange::Amend { .. } => None,
+            })
+            .unwrap_or(Quantity::ZERO)
+    }
+}
+
+/// prices of the YES and the NO share of a trade with the resting order `maker`, its price decides
+pub fn trade_prices(payout: Price, maker: &Order) -> (Price, Price) {
+    let favour_price = match maker.book_side() {
+        Side::Favour => maker.book_price(payout),
+        Side::Against => maker.book_price(payout).complement(payout),
+    };
+    (favour_price, favour_price.complement(payout))
+}
+
+/// gives back what an order set aside for `quantity` of it, the hold of a buy or the reserved shares of a sell
+pub fn release(order: &Order, quantity: Quantity) -> Result<BalanceEffect, Overflow> {
+    Ok(match order.action {
+        Action::Buy => BalanceEffect::Release {
+            user_id: order.user_id.clone(),
+            amount: order.price.checked_mul(quantity)?,
+        },
+        Action::Sell => BalanceEffect::Unreserve {
+            user_id: order.user_id.clone(),
+            side: order.side.clone(),
+            quantity,
+        },
+    })
+}
+
+/**
+ * an amend that only lowers a resting order to `quantity`, the same change replay makes of its journal entry
+ * the order keeps its place, an iceberg gives up its hidden reserve before its slice
+ */
+pub fn amend_in_place(order: &Order, quantity: Quantity) -> BookChange {
+    BookChange::Amend {
+        side: order.book_side(),
+        order_id: order.id.clone(),
+        quantity,
+    }
+}
+
+/// the resting orders `taker` fills against and what self trade prevention cancels, the book is not changed
+fn match_order(
+    book: &OrderBook,
+    taker: &Order,
+    request: &CreateOrderDto,
+    now: DateTime<Utc>,
+) -> Matched {
+    // if someone is willing to buy NO at 80 cents then someone has to buy YES at least at 20 cents or more
+    // so we walk the opposite side from its highest price down to the match price,
+    // oldest order first inside a price level
+    // a sell sits on the side of the other outcome, so it is matched with its book price as well
+    let payout = book.payout;
+    let match_price = taker.book_price(payout).complement(payout);
+    let opposite = book.side(&taker.book_side().opposite());
+    let stp = request.self_trade_prevention.unwrap_or_default();
+    let matched = opposite.find_matches(match_price, taker.quantity, &taker.user_id, now, stp);
+    // fill or kill that can't be filled completely doesn't touch the book at all, the matching itself
+    // decides that, so iceberg slices behind an own order and self trade prevention count the same way
+    if request.time_in_force == TimeInForce::Fok
+        && !(matched.remaining.is_zero() && matched.cancelled.is_zero())
+    {
+        return Matched::unmatched(taker.quantity);
+    }
+    matched
+}
+
+/// what becomes of a post only order before it reaches matching
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PostOnlyCheck {
+    /// it doesn't cross the other side and goes on as it is
+    Rests,
+    /// it would have traded, at this price it rests without trading
+    Repriced(Price),
+    /// it would have traded and is not placed
+    Rejected,
+}
+
+/**
+ * whether a post only `taker` would trade with anything resting at `now`, one that would is either
+ * rejected or moved to the closest price on the grid of the market that doesn't cross the best
+ * price of the other side, a price that falls out of the band is rejected as well
+ */
+pub fn post_only(
+    book: &OrderBook,
+    taker: &Order,
+    params: &MarketParams,
+    mode: PostOnly,
+    now: DateTime<Utc>,
+) -> PostOnlyCheck {
+    let payout = book.payout;
+    let match_price = taker.book_price(payout).complement(payout);
+    let Some(touch) = book.side(&taker.book_side().opposite()).touch(now) else {
+        return PostOnlyCheck::Rests;
+    };
+    if touch < match_price {
+        return PostOnlyCheck::Rests;
+    }
+    if mode == PostOnly::Reject {
+        return PostOnlyCheck::Rejected;
+    }
+    // a buy crosses at `payout - touch` and above, a sell of the outcome at `touch` and below
+    let tick = params.tick_size.get();
+    let price = match taker.action {
+        Action::Buy => (payout.get() - touch.get() - 1).div_euclid(tick) * tick,
+        Action::Sell => (touch.get() + tick).div_euclid(tick) * tick,
+    };
+    let price = Price::new(price);
+    if !params.accepts(price) {
+        return PostOnlyCheck::Rejected;
+    }
+    PostOnlyCheck::Repriced(price)
+}
+
+/**
+ * what one side of a fill does to the accounts of `order`, `price` is the price of the outcome it trades
+ * a buy keeps the hold at the trade price as collateral of its shares, unless it bought them from a seller
+ * whose shares are backed already, then the hold pays the seller
+ * a sell gives up its shares with their collateral and is paid the trade price
+ */
+pub fn fill_effects(
+    effects: &mut Vec<BalanceEffect>,
+    order: &Order,
+    counterparty: Action,
+    price: Price,
+    quantity: Quantity,
+) -> Result<(), Overflow> {
+    match order.action {
+        Action::Buy => {
+            // the hold was taken at the limit price, the difference to a better trade price goes back
+            if order.price > price {
+                effects.push(BalanceEffect::Release {
+                    user_id: order.user_id.clone(),
+                    amount: (order.price - price).checked_mul(quantity)?,
+                });
+            }
+            let cost = price.checked_mul(quantity)?;
+            let mut locked = cost;
+            if counterparty == Action::Sell {
+                effects.push(BalanceEffect::SettleHold {
+                    user_id: order.user_id.clone(),
+                    hold: locked,
+                    payout: Money::ZERO,
+                });
+                locked = Money::ZERO;
+            }
+            effects.push(BalanceEffect::OpenPosition {
+                user_id: order.user_id.clone(),
+                side: order.side.clone(),
+                quantity,
+                locked,
+                cost,
+            });
+        }
+        Action::Sell => effects.push(BalanceEffect::ClosePosition {
+            user_id: order.user_id.clone(),
+            side: order.side.clone(),
+            quantity,
+            proceeds: price.checked_mul(quantity)?,
+        }),
+    }
+    Ok(())
+}
+
+/**
+ * matches `taker` against `book` at `now` and works out the trades, the book changes and the balance effects
+ * `taker` already has its hold or reserved shares, an unfilled remainder that may not rest
+ * (market, ioc, fok) is cancelled and released
+ */
+pub fn execute(
+    book: &OrderBook,
+    taker: &Order,
+    request: &CreateOrderDto,
+    now: DateTime<Utc>,
+) -> Result<Execution, Overflow> {
+    let matched = match_order(book, taker, request, now);
+    let maker_side = taker.book_side().opposite();
+    let mut fills = Vec::with_capacity(matched.fills.len());
+    let mut changes = Vec::new();
+    let mut effects = Vec::new();
+
+    for (maker, quantity) in matched.fills.iter() {
+        let (favour_price, against_price) = trade_prices(book.payout, maker);
+        let fill = Fill {
+            maker: maker.clone(),
+            quantity: *quantity,
+            favour_price,
+            against_price,
+        };
+        let (favour, against) = fill.parties(taker);
+        for (order, counterparty) in [(favour, against), (against, favour)] {
+            fill_effects(
+                &mut effects,
+                order,
+                counterparty.action,
+                fill.price(&order.side),
+                *quantity,
+            )?;
+        }
+        changes.push(BookChange::Reduce {
+            side: maker_side.clone(),
+            order_id: maker.id.clone(),
+            quantity: *quantity,
+        });
+        fills.push(fill);
+    }
+
+    for (resting, cancelled) in matched.self_trade_cancels.iter() {
+        effects.push(release(resting, *cancelled)?);
+        changes.push(BookChange::Reduce {
+            side: maker_side.clone(),
+            order_id: resting.id.clone(),
+            quantity: *cancelled,
+        });
+    }
+
+    let remaining = matched.remaining;
+    let (status, cancelled) = if remaining.is_zero() && matched.cancelled.is_zero() {
+        (OrderStatus::Filled, Quantity::ZERO)
+    } else if !remaining.is_zero() && request.rests_in_book() {
+        let status = if remaining + matched.cancelled < taker.quantity {
+            OrderStatus::PartiallyFilled
+        } else {
+            OrderStatus::Open
+        };
+        let mut rest = taker.clone();
+        rest.quantity = remaining;
+        changes.push(BookChange::Rest(rest));
+        (status, matched.cancelled)
+    } else {
+        (OrderStatus::Cancelled, remaining + matched.cancelled)
+    };
+    if !cancelled.is_zero() {
+        effects.push(release(taker, cancelled)?);
+    }
+
+    Ok(Execution {
+        fills,
+        self_trade_cancels: matched.self_trade_cancels,
+        cancelled,
+        status,
+        book: changes,
+        effects,
+    })
+}
+
+/// brings the book in line with a booked execution
+pub fn apply(book: &mut OrderBook, changes: &[BookChange]) {
+    for change in changes {
+        match change {
+            BookChange::Reduce {
+                side,
+                order_id,
+                quantity,
+            } => {
+                let book_side = book.side_mut(side);
+                let Some(resting) = book_side.get_mut(order_id) else {
+                    continue;
+                };
+                if resting.quantity <= *quantity {
+                    book_side.remove(order_id);
+                } else if resting.reduce(*quantity) {
+                    // the new slice of an iceberg queues behind everything else at its price
+                    let refreshed = book_side
+                        .remove(order_id)
+                        .expect("order was found in the book above");
+                    book.insert(refreshed);
+                }
+            }
+            BookChange::Amend {
+                side,
+                order_id,
+                quantity,
+            } => {
+                if let Some(resting) = book.side_mut(side).get_mut(order_id) {
+                    resting.amend(*quantity);
+                }
+            }
+            BookChange::Rest(order) => book.insert(order.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
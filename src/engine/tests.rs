@@ -0,0 +1,619 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use chrono::Utc;
+use proptest::prelude::*;
+use serde::Deserialize;
+
+use super::*;
+use crate::{
+    journal::{CancelReason, Event, Replay, execution_events},
+    state::{Iceberg, MarketParams, OrderType, SelfTradePrevention},
+};
+
+/**
+ * money and shares of the users of one market the way the db keeps them, just enough to follow
+ * the balance effects of the engine, amounts are plain i64 so a hold that goes negative shows up
+ */
+#[derive(Default)]
+struct Ledger {
+    /// held balance per user, holds of resting buys plus the collateral of open positions
+    holds: HashMap<String, i64>,
+    positions: HashMap<(String, &'static str), Holding>,
+}
+
+#[derive(Default, Debug)]
+struct Holding {
+    quantity: i64,
+    reserved: i64,
+    locked: i64,
+}
+
+impl Ledger {
+    fn free_shares(&self, user_id: &str, side: &Side) -> Quantity {
+        self.positions
+            .get(&(user_id.to_string(), side.as_str()))
+            .map(|holding| Quantity::new(holding.quantity - holding.reserved))
+            .unwrap_or(Quantity::ZERO)
+    }
+
+    /// what the order router does before an order reaches the engine
+    fn hold(&mut self, order: &Order) {
+        match order.action {
+            Action::Buy => {
+                *self.holds.entry(order.user_id.clone()).or_default() +=
+                    (order.price.get()) * order.quantity.get();
+            }
+            Action::Sell => {
+                let holding = self
+                    .positions
+                    .entry((order.user_id.clone(), order.side.as_str()))
+                    .or_default();
+                assert!(
+                    holding.quantity - holding.reserved >= order.quantity.get(),
+                    "{} sells shares it doesn't hold",
+                    order.id
+                );
+                holding.reserved += order.quantity.get();
+            }
+        }
+    }
+
+    /// the same arithmetic as the queries behind `book_effect`
+    fn apply(&mut self, effect: &BalanceEffect) {
+        match effect {
+            BalanceEffect::Release { user_id, amount } => {
+                *self.holds.entry(user_id.clone()).or_default() -= amount.get();
+            }
+            BalanceEffect::Unreserve {
+                user_id,
+                side,
+                quantity,
+            } => {
+                self.holding(user_id, side).reserved -= quantity.get();
+            }
+            BalanceEffect::SettleHold { user_id, hold, .. } => {
+                *self.holds.entry(user_id.clone()).or_default() -= hold.get();
+            }
+            BalanceEffect::OpenPosition {
+                user_id,
+                side,
+                quantity,
+                locked,
+                ..
+            } => {
+                let holding = self.holding(user_id, side);
+                holding.quantity += quantity.get();
+                holding.locked += locked.get();
+            }
+            BalanceEffect::ClosePosition {
+                user_id,
+                side,
+                quantity,
+                ..
+            } => {
+                let holding = self.holding(user_id, side);
+                let released = holding.locked * quantity.get() / holding.quantity;
+                holding.quantity -= quantity.get();
+                holding.reserved -= quantity.get();
+                holding.locked -= released;
+                *self.holds.entry(user_id.clone()).or_default() -= released;
+            }
+        }
+    }
+
+    fn holding(&mut self, user_id: &str, side: &Side) -> &mut Holding {
+        self.positions
+            .entry((user_id.to_string(), side.as_str()))
+            .or_default()
+    }
+}
+
+/// resting orders of both sides
+fn resting(book: &OrderBook) -> impl Iterator<Item = &Order> {
+    book.favour.iter().chain(book.against.iter())
+}
+
+fn book_quantity(book: &OrderBook) -> i64 {
+    resting(book).map(|order| order.quantity.get()).sum()
+}
+
+/// a bid of one side that meets a bid of the other would have traded
+fn assert_not_crossed(book: &OrderBook) {
+    if let (Some(favour), Some(against)) = (book.favour.best_price(), book.against.best_price()) {
+        assert!(
+            favour.get() + against.get() < book.payout.get(),
+            "crossed book, best favour {} and best against {} with payout {}",
+            favour,
+            against,
+            book.payout
+        );
+    }
+}
+
+/// every hold is backed by a resting buy or an open position, every reserved share by a resting sell
+fn assert_holds_match_book(book: &OrderBook, ledger: &Ledger) {
+    let mut expected: HashMap<String, i64> = HashMap::new();
+    let mut reserved: HashMap<(String, &'static str), i64> = HashMap::new();
+    for order in resting(book) {
+        match order.action {
+            Action::Buy => {
+                *expected.entry(order.user_id.clone()).or_default() +=
+                    order.price.get() * order.quantity.get()
+            }
+            Action::Sell => {
+                *reserved
+                    .entry((order.user_id.clone(), order.side.as_str()))
+                    .or_default() += order.quantity.get()
+            }
+        }
+    }
+    for ((user_id, side), holding) in ledger.positions.iter() {
+        *expected.entry(user_id.clone()).or_default() += holding.locked;
+        assert!(holding.quantity >= holding.reserved && holding.reserved >= 0);
+        assert_eq!(
+            holding.reserved,
+            reserved
+                .get(&(user_id.clone(), *side))
+                .copied()
+                .unwrap_or(0),
+            "reserved shares of {} {}",
+            user_id,
+            side
+        );
+    }
+    for (user_id, hold) in ledger.holds.iter() {
+        assert_eq!(
+            *hold,
+            expected.get(user_id).copied().unwrap_or(0),
+            "hold of {}",
+            user_id
+        );
+    }
+}
+
+/**
+ * runs one order through the engine the way the market task does and checks the invariants
+ * that hold for any execution, returns the execution for more specific checks
+ */
+fn step(
+    book: &mut OrderBook,
+    ledger: &mut Ledger,
+    taker: &Order,
+    request: &CreateOrderDto,
+) -> Execution {
+    let now = Utc::now();
+    ledger.hold(taker);
+    let before = book_quantity(book);
+    let execution = execute(book, taker, request, now).expect("amounts fit in 64 bits");
+
+    // the same input gives the same execution
+    let again = execute(book, taker, request, now).expect("amounts fit in 64 bits");
+    assert_eq!(format!("{:?}", execution), format!("{:?}", again));
+
+    let mut filled = Quantity::ZERO;
+    for fill in execution.fills.iter() {
+        assert_ne!(
+            fill.maker.user_id, taker.user_id,
+            "{} filled its own order",
+            taker.id
+        );
+        assert!(fill.quantity <= fill.maker.visible());
+        assert_eq!(fill.favour_price + fill.against_price, book.payout);
+        // the taker never trades at a worse price than its limit
+        let price = fill.price(&taker.side);
+        match taker.action {
+            Action::Buy => assert!(price <= taker.price),
+            Action::Sell => assert!(price >= taker.price),
+        }
+        filled += fill.quantity;
+    }
+    assert_eq!(
+        filled + execution.cancelled + execution.resting(),
+        taker.quantity,
+        "quantity of {} is lost or made up",
+        taker.id
+    );
+
+    let self_cancelled: i64 = execution
+        .self_trade_cancels
+        .iter()
+        .map(|(_, quantity)| quantity.get())
+        .sum();
+    for effect in execution.effects.iter() {
+        ledger.apply(effect);
+    }
+    apply(book, &execution.book);
+    assert_eq!(
+        book_quantity(book),
+        before - filled.get() - self_cancelled + execution.resting().get()
+    );
+    assert_not_crossed(book);
+    assert_holds_match_book(book, ledger);
+    for order in resting(book) {
+        if let Some(iceberg) = order.iceberg {
+            assert!(
+                !iceberg.visible.is_zero() && iceberg.visible <= iceberg.display,
+                "{} shows {:?} of a slice of {:?}",
+                order.id,
+                iceberg.visible,
+                iceberg.display
+            );
+        }
+    }
+    execution
+}
+
+/// the price a market order is held and matched at, the router sets it the same way
+fn with_market_price(mut request: CreateOrderDto, params: &MarketParams) -> CreateOrderDto {
+    if request.order_type == OrderType::Market {
+        request.price = params.market_order_price(request.action);
+    }
+    request
+}
+
+#[derive(Debug, Clone)]
+struct Generated {
+    user: usize,
+    side: Side,
+    sell: bool,
+    price: i64,
+    quantity: i64,
+    order_type: OrderType,
+    time_in_force: TimeInForce,
+    stp: SelfTradePrevention,
+    display: Option<i64>,
+}
+
+fn generated() -> impl Strategy<Value = Generated> {
+    (
+        0..3usize,
+        prop_oneof![Just(Side::Favour), Just(Side::Against)],
+        prop::bool::weighted(0.3),
+        1..=9i64,
+        1..=5i64,
+        prop_oneof![4 => Just(OrderType::Limit), 1 => Just(OrderType::Market)],
+        prop_oneof![
+            3 => Just(TimeInForce::Gtc),
+            1 => Just(TimeInForce::Ioc),
+            1 => Just(TimeInForce::Fok)
+        ],
+        prop_oneof![
+            Just(SelfTradePrevention::CancelNewest),
+            Just(SelfTradePrevention::CancelOldest),
+            Just(SelfTradePrevention::CancelBoth),
+            Just(SelfTradePrevention::DecrementAndCancel)
+        ],
+        prop::option::weighted(0.3, 1..=2i64),
+    )
+        .prop_map(
+            |(user, side, sell, price, quantity, order_type, time_in_force, stp, display)| {
+                Generated {
+                    user,
+                    side,
+                    sell,
+                    price: price * 100,
+                    quantity,
+                    order_type,
+                    time_in_force,
+                    stp,
+                    display,
+                }
+            },
+        )
+}
+
+proptest! {
+    /// random order flow of a few users, sells only for shares the user holds
+    #[test]
+    fn invariants_hold_for_any_order_flow(orders in prop::collection::vec(generated(), 1..60)) {
+        let params = MarketParams::default();
+        let mut book = OrderBook::empty(params.payout);
+        let mut ledger = Ledger::default();
+        let mut replay = Replay::new(params.payout);
+        for (index, generated) in orders.into_iter().enumerate() {
+            let user_id = format!("user{}", generated.user);
+            let mut action = Action::Buy;
+            let mut quantity = Quantity::new(generated.quantity);
+            if generated.sell {
+                let free = ledger.free_shares(&user_id, &generated.side);
+                if !free.is_zero() {
+                    action = Action::Sell;
+                    quantity = quantity.min(free);
+                }
+            }
+            let request = with_market_price(
+                CreateOrderDto {
+                    quantity,
+                    price: Price::new(generated.price),
+                    side: generated.side,
+                    action,
+                    order_type: generated.order_type,
+                    time_in_force: generated.time_in_force,
+                    expires_at: None,
+                    self_trade_prevention: Some(generated.stp),
+                    client_order_id: None,
+                    trigger: None,
+                    post_only: None,
+                    display_quantity: None,
+                },
+                &params,
+            );
+            // the router only takes icebergs that rest and show less than all of their quantity
+            let mut request = request;
+            if request.rests_in_book() {
+                request.display_quantity = generated
+                    .display
+                    .map(Quantity::new)
+                    .filter(|display| *display < quantity);
+            }
+            let taker = request.to_order(format!("o{}", index), user_id);
+            let execution = step(&mut book, &mut ledger, &taker, &request);
+            // the journal entries of every execution, stored and read back, rebuild the same book
+            for event in execution_events(&taker, &execution) {
+                let stored = serde_json::to_string(&event).expect("events serialize");
+                replay.apply(&serde_json::from_str(&stored).expect("events deserialize"));
+            }
+        }
+        // compared as json, the index of a book side is a hash map without an order
+        prop_assert_eq!(
+            serde_json::to_string(replay.book()).expect("books serialize"),
+            serde_json::to_string(&book).expect("books serialize")
+        );
+    }
+}
+
+/**
+ * a scripted matching scenario, orders are sent one after another into an empty book
+ * an order can state what it expects of its execution, the book it leaves is compared at the end
+ */
+#[derive(Deserialize)]
+struct Fixture {
+    #[serde(default)]
+    params: MarketParams,
+    orders: Vec<FixtureOrder>,
+    book: FixtureBook,
+}
+
+#[derive(Deserialize)]
+struct FixtureOrder {
+    id: String,
+    user: String,
+    #[serde(flatten)]
+    request: CreateOrderDto,
+    expect: Option<FixtureExpect>,
+}
+
+#[derive(Deserialize)]
+struct FixtureExpect {
+    status: OrderStatus,
+    #[serde(default)]
+    fills: Vec<FixtureFill>,
+    #[serde(default)]
+    resting: Quantity,
+}
+
+/// one fill at the price of the outcome the incoming order trades
+#[derive(Deserialize, Debug, PartialEq)]
+struct FixtureFill {
+    maker: String,
+    quantity: Quantity,
+    price: Price,
+}
+
+/// resting orders by id and open quantity, in priority order
+#[derive(Deserialize)]
+struct FixtureBook {
+    favour: Vec<FixtureResting>,
+    against: Vec<FixtureResting>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct FixtureResting {
+    id: String,
+    quantity: Quantity,
+}
+
+fn run_fixture(path: &Path) {
+    let name = path.display();
+    let fixture: Fixture = serde_json::from_str(&fs::read_to_string(path).unwrap())
+        .unwrap_or_else(|err| panic!("{}: {}", name, err));
+    let mut book = OrderBook::empty(fixture.params.payout);
+    let mut ledger = Ledger::default();
+    for order in fixture.orders {
+        let request = with_market_price(order.request, &fixture.params);
+        let taker = request.to_order(order.id.clone(), order.user);
+        let execution = step(&mut book, &mut ledger, &taker, &request);
+        let Some(expect) = order.expect else {
+            continue;
+        };
+        assert_eq!(
+            execution.status, expect.status,
+            "{}: status of {}",
+            name, order.id
+        );
+        let fills: Vec<FixtureFill> = execution
+            .fills
+            .iter()
+            .map(|fill| FixtureFill {
+                maker: fill.maker.id.clone(),
+                quantity: fill.quantity,
+                price: fill.price(&taker.side),
+            })
+            .collect();
+        assert_eq!(fills, expect.fills, "{}: fills of {}", name, order.id);
+        assert_eq!(
+            execution.resting(),
+            expect.resting,
+            "{}: resting of {}",
+            name,
+            order.id
+        );
+    }
+
+    let side = |side: &crate::state::BookSide| -> Vec<FixtureResting> {
+        side.iter()
+            .map(|order| FixtureResting {
+                id: order.id.clone(),
+                quantity: order.quantity,
+            })
+            .collect()
+    };
+    assert_eq!(
+        side(&book.favour),
+        fixture.book.favour,
+        "{}: favour side",
+        name
+    );
+    assert_eq!(
+        side(&book.against),
+        fixture.book.against,
+        "{}: against side",
+        name
+    );
+}
+
+#[test]
+fn fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/engine/fixtures");
+    let mut paths: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no fixtures in {}", dir.display());
+    for path in paths {
+        run_fixture(&path);
+    }
+}
+
+#[test]
+fn post_only_rests_behind_the_touch() {
+    let params = MarketParams {
+        tick_size: Price::new(10),
+        ..MarketParams::default()
+    };
+    let now = Utc::now();
+    let mut book = OrderBook::empty(params.payout);
+    book.insert(Order::new(
+        "no400".to_string(),
+        "maker".to_string(),
+        Quantity::new(5),
+        Price::new(400),
+        Side::Against,
+    ));
+    // expired orders wait for the sweeper and don't trade, so they are not the touch
+    let mut expired = Order::new(
+        "no500".to_string(),
+        "maker".to_string(),
+        Quantity::new(5),
+        Price::new(500),
+        Side::Against,
+    );
+    expired.expires_at = Some(now - chrono::Duration::seconds(1));
+    book.insert(expired);
+
+    let order = |price: i64, side: Side, action: Action| {
+        let mut order = Order::new(
+            "taker".to_string(),
+            "taker".to_string(),
+            Quantity::new(1),
+            Price::new(price),
+            side,
+        );
+        order.action = action;
+        order
+    };
+    let check = |order: &Order, mode: PostOnly| post_only(&book, order, &params, mode, now);
+
+    // YES at 600 and above trades with the NO bid at 400
+    let buy = order(650, Side::Favour, Action::Buy);
+    assert_eq!(check(&buy, PostOnly::Reject), PostOnlyCheck::Rejected);
+    assert_eq!(
+        check(&buy, PostOnly::Reprice),
+        PostOnlyCheck::Repriced(Price::new(590))
+    );
+    let buy = order(590, Side::Favour, Action::Buy);
+    assert_eq!(check(&buy, PostOnly::Reject), PostOnlyCheck::Rests);
+
+    // selling NO at 400 and below trades with the same bid
+    let sell = order(350, Side::Against, Action::Sell);
+    assert_eq!(
+        check(&sell, PostOnly::Reprice),
+        PostOnlyCheck::Repriced(Price::new(410))
+    );
+    let sell = order(410, Side::Against, Action::Sell);
+    assert_eq!(check(&sell, PostOnly::Reject), PostOnlyCheck::Rests);
+
+    // behind a NO bid at 900 a YES buy would be below the band
+    book.insert(Order::new(
+        "no900".to_string(),
+        "maker".to_string(),
+        Quantity::new(5),
+        Price::new(900),
+        Side::Against,
+    ));
+    let buy = order(650, Side::Favour, Action::Buy);
+    assert_eq!(
+        post_only(&book, &buy, &params, PostOnly::Reprice, now),
+        PostOnlyCheck::Rejected
+    );
+}
+
+#[test]
+fn amending_an_iceberg_replays_to_the_same_book() {
+    let payout = MarketParams::default().payout;
+    let mut book = OrderBook::empty(payout);
+    let mut replay = Replay::new(payout);
+    let mut iceberg = Order::new(
+        "ice".to_string(),
+        "maker".to_string(),
+        Quantity::new(10),
+        Price::new(500),
+        Side::Favour,
+    );
+    iceberg.iceberg = Some(Iceberg {
+        display: Quantity::new(3),
+        visible: Quantity::new(3),
+    });
+    let behind = Order::new(
+        "behind".to_string(),
+        "other".to_string(),
+        Quantity::new(2),
+        Price::new(500),
+        Side::Favour,
+    );
+    for order in [iceberg, behind] {
+        replay.apply(&Event::Accepted {
+            order: order.clone(),
+        });
+        replay.apply(&Event::Rested {
+            order_id: order.id.clone(),
+            quantity: order.quantity,
+        });
+        book.insert(order);
+    }
+
+    // 8 comes out of the hidden reserve, 2 leaves less than the slice, neither costs the iceberg its place
+    for (quantity, visible) in [(8, 3), (2, 2)] {
+        let quantity = Quantity::new(quantity);
+        let resting = book.get("ice").expect("the iceberg rests").clone();
+        apply(&mut book, &[amend_in_place(&resting, quantity)]);
+        replay.apply(&Event::Cancelled {
+            order_id: resting.id.clone(),
+            quantity: resting.quantity - quantity,
+            reason: CancelReason::Amended,
+        });
+        assert_eq!(
+            serde_json::to_string(replay.book()).expect("books serialize"),
+            serde_json::to_string(&book).expect("books serialize")
+        );
+        assert_eq!(
+            book.favour.iter().next().map(|order| order.id.as_str()),
+            Some("ice")
+        );
+        let ice = book.get("ice").expect("the iceberg rests");
+        assert_eq!(ice.quantity, quantity);
+        assert_eq!(ice.visible(), Quantity::new(visible));
+    }
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use chrono::Utc;
+
+use crate::{
+    db::db::DB,
+    journal::{self, Event},
+    market::{MarketError, release_order},
+    state::OrderBook,
+};
+
+/// how often every market checks its book for good till date orders that ran out
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/**
+ * takes expired orders out of the book and releases the hold or shares of their unfilled quantity
+ * runs inside the market task so an order can't get filled while it is being expired
+ * if the db update fails nothing is removed and the next tick tries again
+ * returns whether the book changed
+ */
+pub async fn expire_orders(db: &DB, opinion_id: &str, book: &mut OrderBook) -> bool {
+    let expired = book.expired(Utc::now());
+    if expired.is_empty() {
+        return false;
+    }
+
+    let released = async {
+        let mut tx = db.pool.begin().await?;
+        for order in expired.iter() {
+            release_order(db, &mut tx, opinion_id, order, order.quantity).await?;
+            db.order.expire(&mut *tx, &order.id).await?;
+            let expired = Event::Expired {
+                order_id: order.id.clone(),
+                quantity: order.quantity,
+            };
+            journal::record(db, &mut tx, opinion_id, &[expired]).await?;
+        }
+        tx.commit().await?;
+        Ok::<_, MarketError>(())
+    };
+
+    if let Err(err) = released.await {
+        eprintln!(
+            "DB error while expiring orders of opinion {}: {:?}",
+            opinion_id, err
+        );
+        return false;
+    }
+
+    for order in expired.iter() {
+        book.remove(&order.id);
+    }
+    true
+}
@@ -0,0 +1,290 @@
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::{
+    db::{db::DB, journal::JournalEntryModel},
+    engine::{self, BookChange, Execution},
+    money::{Price, Quantity},
+    state::{Order, OrderBook},
+};
+
+/**
+ * one entry of the journal of a market, written in the same transaction as what it records
+ * only the book is journaled, a conditional order shows up once it fired and was accepted
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// an order reached matching, placed, fired by its trigger or sent through matching again by an amend
+    Accepted { order: Order },
+    /// the accepted order `order_id` traded with the resting order `maker_id`
+    Fill {
+        order_id: String,
+        maker_id: String,
+        quantity: Quantity,
+        favour_price: Price,
+        against_price: Price,
+    },
+    /// the accepted order `order_id` bought from the market maker, which has no order in the book
+    MakerFill {
+        order_id: String,
+        quantity: Quantity,
+        favour_price: Price,
+        against_price: Price,
+    },
+    /// the unfilled remainder of the accepted order rests in the book
+    Rested {
+        order_id: String,
+        quantity: Quantity,
+    },
+    /// `quantity` of an order is taken out of the market, it leaves the book at zero
+    Cancelled {
+        order_id: String,
+        quantity: Quantity,
+        reason: CancelReason,
+    },
+    /// a good till date order ran out and left the book
+    Expired {
+        order_id: String,
+        quantity: Quantity,
+    },
+    /// the market got its result, everything still resting was released
+    Resolved,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelReason {
+    /// cancelled by the user who placed it
+    User,
+    /// cancelled by an admin, for example on a compromised account
+    Admin,
+    /// a resting order hit by self trade prevention
+    SelfTrade,
+    /// the part of the accepted order that didn't fill and may not rest, or that self trade prevention cut off
+    Unfilled,
+    /// smaller in place, or taken out to go through matching again
+    Amended,
+}
+
+impl Event {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Event::Accepted { .. } => "accepted",
+            Event::Fill { .. } => "fill",
+            Event::MakerFill { .. } => "maker_fill",
+            Event::Rested { .. } => "rested",
+            Event::Cancelled { .. } => "cancelled",
+            Event::Expired { .. } => "expired",
+            Event::Resolved => "resolved",
+        }
+    }
+}
+
+/// the events of one execution of the engine, in the order replay applies them
+pub fn execution_events(taker: &Order, execution: &Execution) -> Vec<Event> {
+    let mut events = vec![Event::Accepted {
+        order: taker.clone(),
+    }];
+    for fill in execution.fills.iter() {
+        events.push(Event::Fill {
+            order_id: taker.id.clone(),
+            maker_id: fill.maker.id.clone(),
+            quantity: fill.quantity,
+            favour_price: fill.favour_price,
+            against_price: fill.against_price,
+        });
+    }
+    for (resting, cancelled) in execution.self_trade_cancels.iter() {
+        events.push(Event::Cancelled {
+            order_id: resting.id.clone(),
+            quantity: *cancelled,
+            reason: CancelReason::SelfTrade,
+        });
+    }
+    if !execution.cancelled.is_zero() {
+        events.push(Event::Cancelled {
+            order_id: taker.id.clone(),
+            quantity: execution.cancelled,
+            reason: CancelReason::Unfilled,
+        });
+    }
+    let resting = execution.resting();
+    if !resting.is_zero() {
+        events.push(Event::Rested {
+            order_id: taker.id.clone(),
+            quantity: resting,
+        });
+    }
+    events
+}
+
+/// appends `events` to the journal of the market through `conn`, they only count once the caller commits
+pub async fn record(
+    db: &DB,
+    conn: &mut PgConnection,
+    opinion_id: &str,
+    events: &[Event],
+) -> Result<(), sqlx::Error> {
+    for event in events {
+        let payload = serde_json::to_string(event).expect("journal events serialize to json");
+        db.journal
+            .append(&mut *conn, opinion_id, event.as_str(), &payload)
+            .await?;
+    }
+    Ok(())
+}
+
+/// builds a book back up from its journal, one event after another
+#[derive(Debug)]
+pub struct Replay {
+    book: OrderBook,
+    /// the last accepted order, it is only in the book once it rests
+    accepted: Option<Order>,
+    /// sequence number of the last applied entry, zero before the first
+    sequence: i64,
+}
+
+impl Replay {
+    pub fn new(payout: Price) -> Self {
+        Self {
+            book: OrderBook::empty(payout),
+            accepted: None,
+            sequence: 0,
+        }
+    }
+
+    /// carries on from a snapshot of `book` taken after the entry `sequence`
+    pub fn resume(book: OrderBook, sequence: i64) -> Self {
+        Self {
+            book,
+            accepted: None,
+            sequence,
+        }
+    }
+
+    pub fn sequence(&self) -> i64 {
+        self.sequence
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    pub fn into_book(self) -> OrderBook {
+        self.book
+    }
+
+    /// applies a stored entry, fails on a payload that isn't an event and leaves the book as it was
+    pub fn apply_entry(&mut self, entry: &JournalEntryModel) -> Result<(), serde_json::Error> {
+        let event = serde_json::from_str(&entry.payload)?;
+        self.apply(&event);
+        self.sequence = entry.sequence;
+        Ok(())
+    }
+
+    pub fn apply(&mut self, event: &Event) {
+        match event {
+            Event::Accepted { order } => self.accepted = Some(order.clone()),
+            Event::Fill {
+                maker_id, quantity, ..
+            } => self.reduce(maker_id, *quantity),
+            // nothing of the maker rests and an order it filled never does either
+            Event::MakerFill { .. } => {}
+            Event::Rested { order_id, quantity } => {
+                let Some(mut order) = self.accepted.take_if(|order| order.id == *order_id) else {
+                    return;
+                };
+                order.quantity = *quantity;
+                engine::apply(&mut self.book, &[BookChange::Rest(order)]);
+            }
+            Event::Cancelled {
+                order_id,
+                quantity,
+                reason: CancelReason::Amended,
+            } => self.amend(order_id, *quantity),
+            // the cancelled part of the accepted order never reached the book
+            Event::Cancelled {
+                order_id, quantity, ..
+            } => self.reduce(order_id, *quantity),
+            Event::Expired { order_id, .. } => {
+                self.book.remove(order_id);
+            }
+            Event::Resolved => {
+                self.book = OrderBook::empty(self.book.payout);
+                self.accepted = None;
+            }
+        }
+    }
+
+    /// takes `quantity` off a resting order, nothing happens for an order that isn't in the book
+    fn reduce(&mut self, order_id: &str, quantity: Quantity) {
+        let Some(resting) = self.book.get(order_id) else {
+            return;
+        };
+        let change = BookChange::Reduce {
+            side: resting.book_side(),
+            order_id: order_id.to_string(),
+            quantity,
+        };
+        engine::apply(&mut self.book, &[change]);
+    }
+
+    /// an amend lowers the order by `quantity` in place, the way the market booked it
+    fn amend(&mut self, order_id: &str, quantity: Quantity) {
+        let Some(resting) = self.book.get(order_id) else {
+            return;
+        };
+        let change = engine::amend_in_place(resting, resting.quantity - quantity);
+        engine::apply(&mut self.book, &[change]);
+    }
+}
+
+/**
+ * `replay <opinion_id> [sequence]` prints the book of the market as it was after the entry `sequence`,
+ * after the last entry without one, for looking into disputes without touching the running server
+ */
+pub async fn replay_tool(args: &[String]) -> Result<(), String> {
+    let Some(opinion_id) = args.first() else {
+        return Err("usage: replay <opinion_id> [sequence]".to_string());
+    };
+    let to = match args.get(1) {
+        Some(sequence) => Some(
+            sequence
+                .parse::<i64>()
+                .map_err(|_| format!("Invalid sequence number {}", sequence))?,
+        ),
+        None => None,
+    };
+
+    let db = DB::new().await;
+    let opinion = db
+        .opinion
+        .find_one(opinion_id.clone())
+        .await
+        .map_err(|_| format!("Market {} not found", opinion_id))?;
+    let entries = db
+        .journal
+        .find_many(Some(opinion_id), to)
+        .await
+        .map_err(|err| format!("DB error while loading the journal: {:?}", err))?;
+
+    let mut replay = Replay::new(opinion.payout);
+    for entry in entries.iter() {
+        replay
+            .apply_entry(entry)
+            .map_err(|err| format!("Invalid journal entry {}: {}", entry.sequence, err))?;
+    }
+    let at = entries.last().map(|entry| entry.created_at);
+    let state = serde_json::json!({
+        "opinionId": opinion_id,
+        "sequence": replay.sequence(),
+        "at": at,
+        "book": replay.book(),
+    });
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&state).expect("book serializes to json")
+    );
+    Ok(())
+}
@@ -0,0 +1,217 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+use crate::{
+    db::{db::DB, opinion::OpinionModel, user::UserLimitsModel},
+    money::{Money, Overflow, Quantity},
+    state::{Action, Order, OrderBook},
+};
+
+/**
+ * caps on what one user may do in one market, a limit that is `None` doesn't apply
+ * markets and accounts both have a set, an order has to stay within the stricter of the two
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Limits {
+    /// largest quantity of a single order
+    pub max_order_size: Option<Quantity>,
+    /// how many orders of the user may rest in the book at the same time
+    pub max_open_orders: Option<i64>,
+    /// most shares of one outcome net of the other, resting buys count as if they were filled
+    pub max_position: Option<Quantity>,
+    /// most money tied up in the market, what the held shares were bought for plus holds of resting buys
+    pub max_notional: Option<Money>,
+}
+
+/// what a market gets unless it is created with its own limits, the order size cap it always had
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_order_size: Some(Quantity::new(5)),
+            max_open_orders: None,
+            max_position: None,
+            max_notional: None,
+        }
+    }
+}
+
+impl From<&OpinionModel> for Limits {
+    fn from(opinion: &OpinionModel) -> Self {
+        Self {
+            max_order_size: opinion.max_order_size,
+            max_open_orders: opinion.max_open_orders,
+            max_position: opinion.max_position,
+            max_notional: opinion.max_notional,
+        }
+    }
+}
+
+impl From<UserLimitsModel> for Limits {
+    fn from(limits: UserLimitsModel) -> Self {
+        Self {
+            max_order_size: limits.max_order_size,
+            max_open_orders: limits.max_open_orders,
+            max_position: limits.max_position,
+            max_notional: limits.max_notional,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LimitError {
+    /// the order would break a limit, the message says which one
+    Exceeded(String),
+    Db(sqlx::Error),
+}
+
+impl From<sqlx::Error> for LimitError {
+    fn from(err: sqlx::Error) -> Self {
+        LimitError::Db(err)
+    }
+}
+
+impl From<Overflow> for LimitError {
+    fn from(err: Overflow) -> Self {
+        LimitError::Exceeded(format!("Order {}", err))
+    }
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::Exceeded(message) => write!(f, "{}", message),
+            LimitError::Db(err) => write!(f, "DB error while checking limits: {}", err),
+        }
+    }
+}
+
+fn stricter<T: Ord>(a: Option<T>, b: Option<T>) -> Option<T> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+impl Limits {
+    pub fn is_valid(&self) -> bool {
+        self.max_order_size.is_none_or(|size| size > Quantity::ZERO)
+            && self.max_open_orders.is_none_or(|count| count > 0)
+            && self.max_position.is_none_or(|size| size > Quantity::ZERO)
+            && self.max_notional.is_none_or(|amount| amount > Money::ZERO)
+    }
+
+    /// the stricter value of every limit
+    pub fn tightest(self, other: Limits) -> Limits {
+        Limits {
+            max_order_size: stricter(self.max_order_size, other.max_order_size),
+            max_open_orders: stricter(self.max_open_orders, other.max_open_orders),
+            max_position: stricter(self.max_position, other.max_position),
+            max_notional: stricter(self.max_notional, other.max_notional),
+        }
+    }
+
+    /**
+     * checks `order` against the user's resting orders in `book` and the shares they already hold,
+     * read through `conn` so the check and the hold of the order are in the same transaction
+     * `pending` are the conditional orders that didn't fire yet, their buys count like resting ones
+     * `rests` says whether the order may end up in the book, `replaces` is the resting order an amend changes,
+     * it is left out as the amended order takes its place
+     * a sell only gives up shares, so the position and notional limits apply to buys only
+     */
+    #[allow(clippy::too_many_arguments)]
+    pub async fn check(
+        &self,
+        db: &DB,
+        conn: &mut PgConnection,
+        opinion_id: &str,
+        book: &OrderBook,
+        pending: &[&Order],
+        order: &Order,
+        rests: bool,
+        replaces: Option<&str>,
+    ) -> Result<(), LimitError> {
+        if let Some(max) = self.max_order_size
+            && order.quantity > max
+        {
+            return Err(LimitError::Exceeded(format!(
+                "Order size {} is above the limit of {} per order",
+                order.quantity, max
+            )));
+        }
+
+        let resting: Vec<&Order> = book
+            .favour
+            .iter()
+            .chain(book.against.iter())
+            .filter(|resting| resting.user_id == order.user_id)
+            .filter(|resting| Some(resting.id.as_str()) != replaces)
+            .collect();
+        if let Some(max) = self.max_open_orders
+            && rests
+            && resting.len() as i64 >= max
+        {
+            return Err(LimitError::Exceeded(format!(
+                "You already have {} open orders in this market, the limit is {}",
+                resting.len(),
+                max
+            )));
+        }
+
+        if order.action == Action::Sell
+            || (self.max_position.is_none() && self.max_notional.is_none())
+        {
+            return Ok(());
+        }
+
+        let positions = db
+            .position
+            .find_by_opinion_and_user_id(conn, opinion_id, &order.user_id)
+            .await?;
+        let mut long = order.quantity;
+        let mut short = Quantity::ZERO;
+        let mut notional = order.price.checked_mul(order.quantity)?;
+        for position in positions.iter() {
+            if position.side == order.side.as_str() {
+                long = long.checked_add(position.quantity)?;
+            } else {
+                short = short.checked_add(position.quantity)?;
+            }
+            notional = notional.checked_add(position.cost)?;
+        }
+        let pending = pending
+            .iter()
+            .filter(|pending| pending.user_id == order.user_id && pending.id != order.id);
+        for resting in resting
+            .iter()
+            .chain(pending)
+            .filter(|resting| resting.action == Action::Buy)
+        {
+            if resting.side == order.side {
+                long = long.checked_add(resting.quantity)?;
+            }
+            notional = notional.checked_add(resting.price.checked_mul(resting.quantity)?)?;
+        }
+
+        let net = long.checked_sub(short).unwrap_or(Quantity::ZERO);
+        if let Some(max) = self.max_position
+            && net > max
+        {
+            return Err(LimitError::Exceeded(format!(
+                "This order would bring your net position to {} shares, the limit is {}",
+                net, max
+            )));
+        }
+        if let Some(max) = self.max_notional
+            && notional > max
+        {
+            return Err(LimitError::Exceeded(format!(
+                "This order would put {} at risk in this market, the limit is {}",
+                notional, max
+            )));
+        }
+        Ok(())
+    }
+}
@@ -1,20 +1,48 @@
+use std::collections::HashMap;
+
 use axum::{Json, Router, response::IntoResponse, routing::get, serve};
 use dotenv::dotenv;
 use routers::router::index_router;
 use serde_json::json;
 use state::AppState;
 use tokio::net::TcpListener;
+mod amm;
+mod conditional;
 mod db;
+mod engine;
+mod expiry;
+mod journal;
+mod limits;
+mod market;
 mod middlewares;
+mod money;
 mod routers;
+mod snapshot;
 mod state;
 use tower_http::cors::{Any, CorsLayer};
 
-use crate::state::OrderBook;
+use crate::{
+    amm::{Lmsr, MarketMaker},
+    conditional::{ConditionalOrder, Trigger, Triggers},
+    db::order::ConditionalOrderModel,
+    journal::Replay,
+    market::spawn_market,
+    snapshot::spawn_snapshots,
+    state::{CreateOrderDto, MarketParams},
+};
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|command| command == "replay") {
+        if let Err(err) = journal::replay_tool(&args[2..]).await {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any) // GET, POST, etc.
@@ -22,6 +50,7 @@ async fn main() {
 
     let app_state = AppState::new().await;
     let app_state = load_data(app_state).await;
+    spawn_snapshots(app_state.markets.clone(), snapshot::dir());
     let router = Router::new()
         .route("/health-check", get(health_check))
         .merge(index_router())
@@ -34,7 +63,7 @@ async fn main() {
 }
 
 async fn health_check() -> impl IntoResponse {
-    return Json(json!({"health":"Route is Healthy"})).into_response();
+    Json(json!({"health":"Route is Healthy"})).into_response()
 }
 
 pub async fn load_data(state: AppState) -> AppState {
@@ -46,14 +75,176 @@ pub async fn load_data(state: AppState) -> AppState {
         }
     };
 
+    // books start from the latest snapshot that reads back whole, only newer journal entries are replayed
+    let mut snapshot = snapshot::load_latest(&snapshot::dir())
+        .await
+        .map(|snapshot| snapshot.markets)
+        .unwrap_or_default();
+    let (snapshot_ids, snapshot_sequences): (Vec<String>, Vec<i64>) = snapshot
+        .iter()
+        .map(|(opinion_id, market)| (opinion_id.clone(), market.sequence))
+        .unzip();
+
+    let journal = match state
+        .db
+        .journal
+        .find_after(&snapshot_ids, &snapshot_sequences)
+        .await
     {
-        let mut order_book = state.order_book.write().await;
-        for opinion in opinions {
-            if let Some(id) = opinion.id.clone() {
-                order_book.insert(id, OrderBook::empty());
-            }
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("DB error while loading the journal: {:?}", err);
+            panic!("DB connection error");
+        }
+    };
+
+    let resting_orders = match state.db.order.find_resting().await {
+        Ok(orders) => orders,
+        Err(err) => {
+            eprintln!("DB error while loading resting orders: {:?}", err);
+            panic!("DB connection error");
+        }
+    };
+
+    let pending_orders = match state.db.order.find_pending_conditionals(None, None).await {
+        Ok(orders) => orders,
+        Err(err) => {
+            eprintln!("DB error while loading conditional orders: {:?}", err);
+            panic!("DB connection error");
+        }
+    };
+
+    let mut last_prices = match state.db.trade.find_last_prices().await {
+        Ok(prices) => prices,
+        Err(err) => {
+            eprintln!("DB error while loading last traded prices: {:?}", err);
+            panic!("DB connection error");
+        }
+    };
+
+    let mut pools: HashMap<_, _> = match state.db.amm.find_many().await {
+        Ok(pools) => pools
+            .into_iter()
+            .map(|pool| (pool.opinion_id.clone(), pool))
+            .collect(),
+        Err(err) => {
+            eprintln!("DB error while loading market makers: {:?}", err);
+            panic!("DB connection error");
+        }
+    };
+
+    let mut order_book = HashMap::new();
+    for opinion in opinions {
+        if let Some(id) = opinion.id.clone() {
+            let params = MarketParams::from(&opinion);
+            let triggers = Triggers::new(last_prices.remove(&id), vec![]);
+            let replay = match snapshot.remove(&id) {
+                Some(market) => Replay::resume(market.book, market.sequence),
+                None => Replay::new(params.payout),
+            };
+            order_book.insert(id, (params, replay, triggers));
+        }
+    }
+
+    // entries come sorted by market and sequence number
+    for entry in journal.iter() {
+        let Some((_, replay, _)) = order_book.get_mut(&entry.opinion_id) else {
+            continue;
+        };
+        if let Err(err) = replay.apply_entry(entry) {
+            eprintln!(
+                "Skipping journal entry {} of opinion {}: {}",
+                entry.sequence, entry.opinion_id, err
+            );
+        }
+    }
+
+    // the journal is written with the orders, a resting order that doesn't match the replayed book means one of them was changed by hand
+    for resting in resting_orders {
+        let Some((_, replay, _)) = order_book.get(&resting.opinion_id) else {
+            continue;
+        };
+        let quantity = replay.book().get(&resting.id).map(|order| order.quantity);
+        if quantity != Some(resting.remaining_quantity) {
+            eprintln!(
+                "Order {} rests with {:?} in the orders table but replays to {:?} at journal entry {}",
+                resting.id,
+                resting.remaining_quantity,
+                quantity,
+                replay.sequence()
+            );
+        }
+    }
+
+    // conditional orders come oldest first, the order they fire in
+    for pending in pending_orders {
+        let Some((_, _, triggers)) = order_book.get_mut(&pending.opinion_id) else {
+            continue;
+        };
+        let order_id = pending.id.clone();
+        match conditional_order(pending) {
+            Ok(conditional) => triggers.add(conditional),
+            Err(err) => eprintln!("Skipping conditional order {}: {}", order_id, err),
+        }
+    }
+
+    // every market gets its own task that owns its book from now on
+    {
+        let mut markets = state.markets.write().await;
+        for (opinion_id, (params, replay, triggers)) in order_book {
+            let book = replay.into_book();
+            // the maker carries on with the shares it sold so far
+            let maker = params
+                .liquidity
+                .zip(pools.remove(&opinion_id))
+                .map(|(liquidity, pool)| MarketMaker {
+                    owner_id: pool.owner_id,
+                    lmsr: Lmsr {
+                        liquidity,
+                        yes: pool.yes_shares,
+                        no: pool.no_shares,
+                    },
+                });
+            let market = spawn_market(
+                state.db.clone(),
+                opinion_id.clone(),
+                params,
+                book,
+                triggers,
+                maker,
+            );
+            markets.insert(opinion_id, market);
         }
     }
 
     state
 }
+
+/// a stored pending order as the market keeps it, with the request it turns into once it fires
+fn conditional_order(pending: ConditionalOrderModel) -> Result<ConditionalOrder, String> {
+    let trigger = Trigger {
+        price: pending.trigger_price,
+        direction: pending.trigger_direction.parse()?,
+    };
+    let request = CreateOrderDto {
+        quantity: pending.quantity,
+        price: pending.price,
+        side: pending.side.parse()?,
+        action: pending.action.parse()?,
+        order_type: pending.order_type.parse()?,
+        time_in_force: pending.time_in_force.parse()?,
+        expires_at: None,
+        self_trade_prevention: Some(pending.self_trade_prevention.parse()?),
+        client_order_id: pending.client_order_id,
+        trigger: Some(trigger),
+        post_only: None,
+        display_quantity: None,
+    };
+    let mut order = request.to_order(pending.id, pending.user_id);
+    order.created_at = pending.created_at;
+    Ok(ConditionalOrder {
+        order,
+        request,
+        trigger,
+    })
+}
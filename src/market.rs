@@ -0,0 +1,1452 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::{PgConnection, Postgres, Transaction};
+use tokio::sync::{mpsc, oneshot, watch};
+
+use crate::{
+    amm::{Lmsr, MarketMaker},
+    conditional::{ConditionalOrder, Trigger, Triggers},
+    db::{
+        db::DB,
+        order::{OrderModel, OrderStatus},
+        trade::TradeModel,
+    },
+    engine::{self, BalanceEffect, Execution, PostOnlyCheck},
+    expiry::{SWEEP_INTERVAL, expire_orders},
+    journal::{self, CancelReason, Event},
+    limits::{LimitError, Limits},
+    money::{Overflow, Price, Quantity},
+    snapshot::MarketSnapshot,
+    state::{
+        Action, BookView, CreateOrderDto, MarketParams, Order, OrderBook, OrderType, Side,
+        TimeInForce,
+    },
+};
+
+/// how many commands can wait for a market before senders have to wait as well
+const COMMAND_BUFFER: usize = 1024;
+
+/**
+ * every market runs in its own task that owns its order book and handles commands one after another,
+ * so matching and settling an order can't interleave with anything else in the same market
+ * while different markets run in parallel
+ */
+enum Command {
+    Place {
+        taker: Order,
+        order: CreateOrderDto,
+        reply: oneshot::Sender<Result<Placement, MarketError>>,
+    },
+    /// every order of a batch, the whole batch is rejected if one of them can't be held
+    PlaceBatch {
+        orders: Vec<(Order, CreateOrderDto)>,
+        reply: oneshot::Sender<Result<Vec<Result<Placement, MarketError>>, MarketError>>,
+    },
+    /// a conditional order, it fires right away if the market already traded past its trigger
+    AddConditional {
+        conditional: ConditionalOrder,
+        reply: oneshot::Sender<Result<Placement, MarketError>>,
+    },
+    Cancel {
+        order_id: String,
+        user_id: String,
+        reply: oneshot::Sender<Result<Order, MarketError>>,
+    },
+    /// every resting and pending order of `user_id`, of everyone without one
+    CancelAll {
+        user_id: Option<String>,
+        reason: CancelReason,
+        reply: oneshot::Sender<Result<Vec<Order>, MarketError>>,
+    },
+    Amend {
+        order_id: String,
+        user_id: String,
+        price: Option<Price>,
+        quantity: Option<Quantity>,
+        reply: oneshot::Sender<Result<Amended, MarketError>>,
+    },
+    /// the book with the sequence number of the last journal entry it contains
+    Snapshot {
+        reply: oneshot::Sender<Result<MarketSnapshot, MarketError>>,
+    },
+    /// releases everything resting in the book and stops the market
+    Resolve {
+        reply: oneshot::Sender<Result<(), MarketError>>,
+    },
+}
+
+#[derive(Debug)]
+pub enum MarketError {
+    OrderNotFound,
+    NotOwner,
+    InsufficientBalance,
+    InsufficientShares,
+    /// the market task is gone, the market got resolved
+    Closed,
+    /// an amount didn't fit in 64 bits, nothing was booked
+    Overflow,
+    /// a post only order would have traded, it was not placed
+    WouldTake,
+    /// the order would break a limit of the market or the account, the message says which one
+    LimitExceeded(String),
+    /// the order at `index` of a batch was refused, so nothing of the batch was placed
+    Rejected {
+        index: usize,
+        error: Box<MarketError>,
+    },
+    Db(sqlx::Error),
+}
+
+impl From<sqlx::Error> for MarketError {
+    fn from(err: sqlx::Error) -> Self {
+        MarketError::Db(err)
+    }
+}
+
+impl From<Overflow> for MarketError {
+    fn from(_: Overflow) -> Self {
+        MarketError::Overflow
+    }
+}
+
+impl From<LimitError> for MarketError {
+    fn from(err: LimitError) -> Self {
+        match err {
+            LimitError::Exceeded(message) => MarketError::LimitExceeded(message),
+            LimitError::Db(err) => MarketError::Db(err),
+        }
+    }
+}
+
+/// what became of a placed order
+#[derive(Debug, Serialize)]
+pub struct Placement {
+    pub status: OrderStatus,
+    /// one per resting order it traded with, in the order they were matched
+    pub fills: Vec<Fill>,
+    /// quantity left in the book, zero if nothing of the order rests
+    pub resting: Quantity,
+    /// the price a post only order was moved to so it doesn't trade
+    pub repriced: Option<Price>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Fill {
+    /// price of the outcome the order buys or sells, set by the resting order
+    pub price: Price,
+    pub quantity: Quantity,
+}
+
+/// what an amend did to the order
+pub enum Amended {
+    /// same price and quantity as before
+    Unchanged(Order),
+    /// smaller quantity, the order kept its place in the queue unless its iceberg slice ran out
+    InPlace(Order),
+    /// the order went through matching again with its new price or size
+    Requeued(Placement),
+}
+
+/// cheap to clone handle to a running market
+#[derive(Clone)]
+pub struct MarketHandle {
+    commands: mpsc::Sender<Command>,
+    snapshot: watch::Receiver<Arc<BookView>>,
+    maker: watch::Receiver<Option<Lmsr>>,
+    params: MarketParams,
+}
+
+impl MarketHandle {
+    /// payout and price grid of the market, they don't change while it runs
+    pub fn params(&self) -> &MarketParams {
+        &self.params
+    }
+
+    /// the best levels of the book as it was after the last change, never in the middle of a command
+    pub fn book(&self) -> Arc<BookView> {
+        self.snapshot.borrow().clone()
+    }
+
+    /// shares the market maker sold as of the last command, `None` for a market without one
+    pub fn maker(&self) -> Option<Lmsr> {
+        *self.maker.borrow()
+    }
+
+    async fn send<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<Result<T, MarketError>>) -> Command,
+    ) -> Result<T, MarketError> {
+        let (reply, response) = oneshot::channel();
+        self.commands
+            .send(command(reply))
+            .await
+            .map_err(|_| MarketError::Closed)?;
+        response.await.map_err(|_| MarketError::Closed)?
+    }
+
+    /**
+     * checks the order against the limits, takes its hold and matches it,
+     * the remainder rests or gets cancelled by its time in force
+     */
+    pub async fn place(
+        &self,
+        taker: Order,
+        order: CreateOrderDto,
+    ) -> Result<Placement, MarketError> {
+        self.send(|reply| Command::Place {
+            taker,
+            order,
+            reply,
+        })
+        .await
+    }
+
+    /// places the orders of a batch one after another, each with its own result
+    pub async fn place_batch(
+        &self,
+        orders: Vec<(Order, CreateOrderDto)>,
+    ) -> Result<Vec<Result<Placement, MarketError>>, MarketError> {
+        self.send(|reply| Command::PlaceBatch { orders, reply })
+            .await
+    }
+
+    /// hands a conditional order to the market with its hold, it stays pending unless it fired at once
+    pub async fn add_conditional(
+        &self,
+        order: Order,
+        request: CreateOrderDto,
+        trigger: Trigger,
+    ) -> Result<Placement, MarketError> {
+        self.send(|reply| Command::AddConditional {
+            conditional: ConditionalOrder {
+                order,
+                request,
+                trigger,
+            },
+            reply,
+        })
+        .await
+    }
+
+    /// cancels a resting order or a conditional one that didn't fire yet
+    pub async fn cancel(&self, order_id: String, user_id: String) -> Result<Order, MarketError> {
+        self.send(|reply| Command::Cancel {
+            order_id,
+            user_id,
+            reply,
+        })
+        .await
+    }
+
+    /// cancels the resting and pending orders of `user_id`, or all of them, returns what was cancelled
+    pub async fn cancel_all(
+        &self,
+        user_id: Option<String>,
+        reason: CancelReason,
+    ) -> Result<Vec<Order>, MarketError> {
+        self.send(|reply| Command::CancelAll {
+            user_id,
+            reason,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn amend(
+        &self,
+        order_id: String,
+        user_id: String,
+        price: Option<Price>,
+        quantity: Option<Quantity>,
+    ) -> Result<Amended, MarketError> {
+        self.send(|reply| Command::Amend {
+            order_id,
+            user_id,
+            price,
+            quantity,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn snapshot(&self) -> Result<MarketSnapshot, MarketError> {
+        self.send(|reply| Command::Snapshot { reply }).await
+    }
+
+    pub async fn resolve(&self) -> Result<(), MarketError> {
+        self.send(|reply| Command::Resolve { reply }).await
+    }
+}
+
+/**
+ * starts the task of one market with the orders already resting in it and the conditional orders waiting for it
+ * a market with a `maker` fills every order from it instead of the book
+ */
+pub fn spawn_market(
+    db: DB,
+    opinion_id: String,
+    params: MarketParams,
+    book: OrderBook,
+    triggers: Triggers,
+    maker: Option<MarketMaker>,
+) -> MarketHandle {
+    let (commands, receiver) = mpsc::channel(COMMAND_BUFFER);
+    let (snapshot, snapshot_receiver) = watch::channel(Arc::new(book.view(Utc::now())));
+    let (maker_snapshot, maker_receiver) = watch::channel(maker.as_ref().map(|maker| maker.lmsr));
+    let market = Market {
+        db,
+        opinion_id,
+        params,
+        book,
+        triggers,
+        maker,
+        snapshot,
+        maker_snapshot,
+    };
+    tokio::spawn(market.run(receiver));
+    MarketHandle {
+        commands,
+        snapshot: snapshot_receiver,
+        maker: maker_receiver,
+        params,
+    }
+}
+
+struct Market {
+    db: DB,
+    opinion_id: String,
+    params: MarketParams,
+    book: OrderBook,
+    triggers: Triggers,
+    maker: Option<MarketMaker>,
+    snapshot: watch::Sender<Arc<BookView>>,
+    maker_snapshot: watch::Sender<Option<Lmsr>>,
+}
+
+impl Market {
+    async fn run(mut self, mut commands: mpsc::Receiver<Command>) {
+        let mut expiry = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            tokio::select! {
+                command = commands.recv() => {
+                    let Some(command) = command else { break };
+                    if !self.handle(command).await {
+                        break;
+                    }
+                    self.fire_triggers().await;
+                }
+                _ = expiry.tick() => {
+                    if !expire_orders(&self.db, &self.opinion_id, &mut self.book).await {
+                        continue;
+                    }
+                }
+            }
+            self.publish();
+        }
+    }
+
+    /// hands the routes the new view of the book and the maker, readers aren't woken for what didn't change
+    fn publish(&self) {
+        let view = self.book.view(Utc::now());
+        self.snapshot.send_if_modified(|published| {
+            if **published == view {
+                return false;
+            }
+            *published = Arc::new(view);
+            true
+        });
+        let lmsr = self.maker.as_ref().map(|maker| maker.lmsr);
+        self.maker_snapshot.send_if_modified(|published| {
+            if *published == lmsr {
+                return false;
+            }
+            *published = lmsr;
+            true
+        });
+    }
+
+    /// returns false once the market is done and the task should stop
+    async fn handle(&mut self, command: Command) -> bool {
+        match command {
+            Command::Place {
+                taker,
+                order,
+                reply,
+            } => {
+                let _ = reply.send(self.place(taker, &order).await);
+            }
+            Command::PlaceBatch { orders, reply } => {
+                let _ = reply.send(self.place_batch(orders).await);
+            }
+            Command::AddConditional { conditional, reply } => {
+                let _ = reply.send(self.add_conditional(conditional).await);
+            }
+            Command::Cancel {
+                order_id,
+                user_id,
+                reply,
+            } => {
+                let _ = reply.send(self.cancel(&order_id, &user_id).await);
+            }
+            Command::CancelAll {
+                user_id,
+                reason,
+                reply,
+            } => {
+                let cancelled = self.cancel_all(user_id.as_deref(), reason).await;
+                let _ = reply.send(cancelled);
+            }
+            Command::Amend {
+                order_id,
+                user_id,
+                price,
+                quantity,
+                reply,
+            } => {
+                let amended = self.amend(&order_id, &user_id, price, quantity).await;
+                let _ = reply.send(amended);
+            }
+            Command::Snapshot { reply } => {
+                let _ = reply.send(self.take_snapshot().await);
+            }
+            Command::Resolve { reply } => {
+                let resolved = self.resolve().await;
+                let stop = resolved.is_ok();
+                let _ = reply.send(resolved);
+                return !stop;
+            }
+        }
+        true
+    }
+
+    /**
+     * find matching orders for the incoming one and book everything it caused in one transaction,
+     * together with the limit check and the hold of the order,
+     * the book only changes once that transaction is committed, so a failed settlement leaves both untouched
+     */
+    async fn place(
+        &mut self,
+        taker: Order,
+        order: &CreateOrderDto,
+    ) -> Result<Placement, MarketError> {
+        let mut tx = self.db.pool.begin().await?;
+        self.hold(&mut tx, &self.book, &self.pending(), &taker, order)
+            .await?;
+        let placed = self.execute(tx, taker.clone(), order, None).await;
+        if placed.is_err() {
+            self.record_failed(&taker, order).await;
+        }
+        placed
+    }
+
+    /**
+     * places the orders of a batch one after another, the limits are checked and the holds are taken
+     * for all of them in one transaction up front, so a batch that doesn't fit is rejected as a whole
+     * later orders see the earlier ones as resting, whether they end up filled or not
+     */
+    async fn place_batch(
+        &mut self,
+        orders: Vec<(Order, CreateOrderDto)>,
+    ) -> Result<Vec<Result<Placement, MarketError>>, MarketError> {
+        let mut tx = self.db.pool.begin().await?;
+        let mut book = self.book.clone();
+        let mut pending = self.pending();
+        for (index, (taker, order)) in orders.iter().enumerate() {
+            match self.hold(&mut tx, &book, &pending, taker, order).await {
+                Ok(()) => {}
+                Err(MarketError::Db(err)) => return Err(MarketError::Db(err)),
+                Err(err) => {
+                    return Err(MarketError::Rejected {
+                        index,
+                        error: Box::new(err),
+                    });
+                }
+            }
+            if order.trigger.is_some() {
+                pending.push(taker);
+            } else if order.rests_in_book() {
+                book.insert(taker.clone());
+            }
+        }
+        tx.commit().await?;
+
+        let mut placements = Vec::with_capacity(orders.len());
+        for (taker, order) in orders {
+            // the holds are committed already, an order that can't even get a transaction fails on its own
+            // instead of leaving the rest of the batch open with their holds
+            let placed = match self.db.pool.begin().await {
+                Ok(tx) => match order.trigger {
+                    Some(trigger) => {
+                        let conditional = ConditionalOrder {
+                            order: taker.clone(),
+                            request: order,
+                            trigger,
+                        };
+                        self.enter_conditional(tx, conditional).await
+                    }
+                    None => self.execute(tx, taker.clone(), &order, None).await,
+                },
+                Err(err) => Err(err.into()),
+            };
+            // nothing of the order got booked, the whole hold goes back
+            if placed.is_err()
+                && let Err(err) = self.release_and_cancel(&taker).await
+            {
+                eprintln!(
+                    "DB error while releasing order {} after a failed placement: {:?}",
+                    taker.id, err
+                );
+            }
+            placements.push(placed);
+            self.fire_triggers().await;
+        }
+        Ok(placements)
+    }
+
+    /// the stricter of the market limits and the limits of the account of `user_id`
+    async fn limits(
+        &self,
+        conn: &mut PgConnection,
+        user_id: &String,
+    ) -> Result<Limits, MarketError> {
+        let account = self.db.user.get_limits(conn, user_id).await?;
+        Ok(self.params.limits.tightest(account.into()))
+    }
+
+    /// the conditional orders waiting for their trigger, they count towards the limits of their users
+    fn pending(&self) -> Vec<&Order> {
+        self.triggers.iter().map(|pending| &pending.order).collect()
+    }
+
+    /// checks a new order against the limits, `book` and the `pending` conditional orders, then holds and records it, all through `conn`
+    async fn hold(
+        &self,
+        conn: &mut PgConnection,
+        book: &OrderBook,
+        pending: &[&Order],
+        taker: &Order,
+        order: &CreateOrderDto,
+    ) -> Result<(), MarketError> {
+        let limits = self.limits(&mut *conn, &taker.user_id).await?;
+        limits
+            .check(
+                &self.db,
+                &mut *conn,
+                &self.opinion_id,
+                book,
+                pending,
+                taker,
+                order.rests_in_book(),
+                None,
+            )
+            .await?;
+        hold_and_record_order(
+            &self.db,
+            conn,
+            &taker.id,
+            &taker.user_id,
+            &self.opinion_id,
+            order,
+        )
+        .await
+    }
+
+    /**
+     * an order whose placement was rolled back with its hold is kept as cancelled,
+     * so a request sent again with its client order id finds it and gets the same answer
+     */
+    async fn record_failed(&self, taker: &Order, order: &CreateOrderDto) {
+        if let Err(err) = record_order(
+            &self.db,
+            &self.db.pool,
+            &taker.id,
+            &taker.user_id,
+            &self.opinion_id,
+            order,
+            OrderStatus::Cancelled,
+        )
+        .await
+        {
+            eprintln!(
+                "DB error while recording failed order {}: {:?}",
+                taker.id, err
+            );
+        }
+    }
+
+    /// undoes the hold of an order that was held but never made it into the market
+    async fn release_and_cancel(&self, order: &Order) -> Result<(), MarketError> {
+        let db = &self.db;
+        let mut tx = db.pool.begin().await?;
+        release_order(db, &mut tx, &self.opinion_id, order, order.quantity).await?;
+        db.order.cancel(&mut *tx, &order.id).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /**
+     * matching and settlement of `place` in a transaction that may hold more changes to the order, it gets committed here
+     * `replaces` is the resting order an amend sends through matching again, it leaves the book with the commit
+     */
+    async fn execute(
+        &mut self,
+        mut tx: Transaction<'static, Postgres>,
+        taker: Order,
+        order: &CreateOrderDto,
+        replaces: Option<&str>,
+    ) -> Result<Placement, MarketError> {
+        if self.maker.is_some() {
+            return self.buy_from_maker(tx, taker, order).await;
+        }
+        let now = Utc::now();
+        let mut taker = taker;
+        let mut repriced = None;
+        if let Some(mode) = order.post_only {
+            match engine::post_only(&self.book, &taker, &self.params, mode, now) {
+                PostOnlyCheck::Rests => {}
+                PostOnlyCheck::Repriced(price) => {
+                    self.reprice(&mut tx, &taker, price).await?;
+                    taker.price = price;
+                    repriced = Some(price);
+                }
+                PostOnlyCheck::Rejected => return Err(MarketError::WouldTake),
+            }
+        }
+        let execution = engine::execute(&self.book, &taker, order, now)?;
+        let trades = self.settle(&mut tx, &taker, &execution).await?;
+        tx.commit().await?;
+        for trade in trades.iter() {
+            self.triggers.record(trade);
+        }
+
+        let fills = execution
+            .fills
+            .iter()
+            .map(|fill| Fill {
+                price: fill.price(&taker.side),
+                quantity: fill.quantity,
+            })
+            .collect();
+        if let Some(order_id) = replaces {
+            self.book.remove(order_id);
+        }
+        engine::apply(&mut self.book, &execution.book);
+        Ok(Placement {
+            status: execution.status,
+            fills,
+            resting: execution.resting(),
+            repriced,
+        })
+    }
+
+    /// moves a post only order to `price` before it is matched, a buy gets back the hold above the new price
+    async fn reprice(
+        &self,
+        conn: &mut PgConnection,
+        taker: &Order,
+        price: Price,
+    ) -> Result<(), MarketError> {
+        if taker.action == Action::Buy && price < taker.price {
+            let released = (taker.price - price).checked_mul(taker.quantity)?;
+            self.db
+                .user
+                .release_balance(&mut *conn, &taker.user_id, released)
+                .await?;
+        }
+        self.db
+            .order
+            .amend(&mut *conn, &taker.id, price, taker.quantity, false)
+            .await?;
+        Ok(())
+    }
+
+    /**
+     * fills a buy from the market maker, all of it at one price, the part the maker doesn't sell
+     * within the limit of the order is cancelled as nothing rests in a market with a maker
+     * the maker is on the other side of the trade as a sell without an order, what the buyer pays goes to its pool
+     * the order and its fill are journaled all the same, they just leave the book as it is
+     */
+    async fn buy_from_maker(
+        &mut self,
+        mut tx: Transaction<'static, Postgres>,
+        taker: Order,
+        order: &CreateOrderDto,
+    ) -> Result<Placement, MarketError> {
+        let maker = self
+            .maker
+            .as_ref()
+            .expect("only markets with a maker buy from it");
+        let mut fill = maker
+            .lmsr
+            .buy(&taker.side, taker.quantity, taker.price, &self.params);
+        if order.time_in_force == TimeInForce::Fok && fill.quantity < taker.quantity {
+            fill.quantity = Quantity::ZERO;
+        }
+        let unfilled = taker.quantity - fill.quantity;
+
+        let db = &self.db;
+        let mut effects = Vec::new();
+        let mut trade = None;
+        let mut events = vec![Event::Accepted {
+            order: taker.clone(),
+        }];
+        if !fill.quantity.is_zero() {
+            let owner_id = maker.owner_id.clone();
+            let other_price = fill.price.complement(self.params.payout);
+            let opinion_id = self.opinion_id.clone();
+            let quantity = fill.quantity;
+            // the maker sells the outcome the order buys, a sold YES is booked on the against side
+            let mut model = match taker.side {
+                Side::Favour => {
+                    let mut model = TradeModel::new(
+                        None,
+                        opinion_id,
+                        taker.user_id.clone(),
+                        owner_id,
+                        fill.price,
+                        other_price,
+                        quantity,
+                    );
+                    model.favour_order_id = Some(taker.id.clone());
+                    model.against_action = Action::Sell.as_str().to_string();
+                    model
+                }
+                Side::Against => {
+                    let mut model = TradeModel::new(
+                        None,
+                        opinion_id,
+                        owner_id,
+                        taker.user_id.clone(),
+                        other_price,
+                        fill.price,
+                        quantity,
+                    );
+                    model.against_order_id = Some(taker.id.clone());
+                    model.favour_action = Action::Sell.as_str().to_string();
+                    model
+                }
+            };
+            model.taker_side = Some(taker.side.as_str().to_string());
+            db.trade.create(&mut *tx, &model).await?;
+            db.order.fill(&mut *tx, &taker.id, fill.quantity).await?;
+            db.amm
+                .sell(
+                    &mut *tx,
+                    &self.opinion_id,
+                    &taker.side,
+                    fill.quantity,
+                    fill.price.checked_mul(fill.quantity)?,
+                )
+                .await?;
+            engine::fill_effects(
+                &mut effects,
+                &taker,
+                Action::Sell,
+                fill.price,
+                fill.quantity,
+            )?;
+            events.push(Event::MakerFill {
+                order_id: taker.id.clone(),
+                quantity: fill.quantity,
+                favour_price: model.favour_price,
+                against_price: model.against_price,
+            });
+            trade = Some(model);
+        }
+        if !unfilled.is_zero() {
+            effects.push(engine::release(&taker, unfilled)?);
+            db.order.cancel(&mut *tx, &taker.id).await?;
+            events.push(Event::Cancelled {
+                order_id: taker.id.clone(),
+                quantity: unfilled,
+                reason: CancelReason::Unfilled,
+            });
+        }
+        for effect in effects.iter() {
+            book_effect(db, &mut tx, &self.opinion_id, effect).await?;
+        }
+        journal::record(db, &mut tx, &self.opinion_id, &events).await?;
+        tx.commit().await?;
+
+        let mut fills = Vec::new();
+        if let Some(trade) = trade {
+            self.triggers.record(&trade);
+            fills.push(Fill {
+                price: fill.price,
+                quantity: fill.quantity,
+            });
+        }
+        if let Some(maker) = self.maker.as_mut() {
+            maker.lmsr.sell(&taker.side, fill.quantity);
+        }
+        Ok(Placement {
+            status: if unfilled.is_zero() {
+                OrderStatus::Filled
+            } else {
+                OrderStatus::Cancelled
+            },
+            fills,
+            resting: Quantity::ZERO,
+            repriced: None,
+        })
+    }
+
+    /**
+     * checks a conditional order against the limits and takes its hold, the same as for an order placed right away
+     * so it can't fail for lack of money once it fires
+     */
+    async fn add_conditional(
+        &mut self,
+        conditional: ConditionalOrder,
+    ) -> Result<Placement, MarketError> {
+        let mut tx = self.db.pool.begin().await?;
+        self.hold(
+            &mut tx,
+            &self.book,
+            &self.pending(),
+            &conditional.order,
+            &conditional.request,
+        )
+        .await?;
+        let taker = conditional.order.clone();
+        let request = conditional.request.clone();
+        let placed = self.enter_conditional(tx, conditional).await;
+        if placed.is_err() {
+            self.record_failed(&taker, &request).await;
+        }
+        placed
+    }
+
+    /**
+     * keeps a conditional order held in `tx` until its trigger fires, one whose trigger already fired at the
+     * last traded price is placed right away in the same transaction
+     */
+    async fn enter_conditional(
+        &mut self,
+        mut tx: Transaction<'static, Postgres>,
+        conditional: ConditionalOrder,
+    ) -> Result<Placement, MarketError> {
+        let payout = self.book.payout;
+        if self
+            .triggers
+            .fires(&conditional.trigger, &conditional.order.side, payout)
+        {
+            self.db
+                .order
+                .activate(&mut *tx, &conditional.order.id)
+                .await?;
+            return self
+                .execute(tx, conditional.order, &conditional.request, None)
+                .await;
+        }
+        tx.commit().await?;
+        self.triggers.add(conditional);
+        Ok(Placement {
+            status: OrderStatus::Pending,
+            fills: vec![],
+            resting: Quantity::ZERO,
+            repriced: None,
+        })
+    }
+
+    /**
+     * the pending order enters the market the same way a new order does
+     * the user may have traded since it was held, so it has to fit the limits again,
+     * one that doesn't is cancelled and its hold goes back
+     */
+    async fn trigger(&mut self, conditional: &ConditionalOrder) -> Result<Placement, MarketError> {
+        let mut tx = self.db.pool.begin().await?;
+        let order = &conditional.order;
+        let limits = self.limits(&mut tx, &order.user_id).await?;
+        let checked = limits
+            .check(
+                &self.db,
+                &mut tx,
+                &self.opinion_id,
+                &self.book,
+                &self.pending(),
+                order,
+                conditional.request.rests_in_book(),
+                None,
+            )
+            .await;
+        if let Err(err) = checked {
+            tx.rollback().await?;
+            if let LimitError::Exceeded(_) = err {
+                self.release_and_cancel(order).await?;
+            }
+            return Err(err.into());
+        }
+        self.db
+            .order
+            .activate(&mut *tx, &conditional.order.id)
+            .await?;
+        self.execute(tx, conditional.order.clone(), &conditional.request, None)
+            .await
+    }
+
+    /**
+     * places every conditional order the last traded price fired, their trades can fire more of them
+     * an order that fails to go through stays pending and is tried again after the next command,
+     * unless it broke the limits, that one is cancelled for good
+     */
+    async fn fire_triggers(&mut self) {
+        let payout = self.book.payout;
+        let mut failed = Vec::new();
+        loop {
+            let fired = self.triggers.take_fired(payout);
+            if fired.is_empty() {
+                break;
+            }
+            for conditional in fired {
+                match self.trigger(&conditional).await {
+                    Ok(_) => {}
+                    Err(MarketError::LimitExceeded(message)) => {
+                        eprintln!(
+                            "Cancelled triggered order {}: {}",
+                            conditional.order.id, message
+                        );
+                    }
+                    Err(err) => {
+                        eprintln!(
+                            "Error while placing triggered order {}: {:?}",
+                            conditional.order.id, err
+                        );
+                        failed.push(conditional);
+                    }
+                }
+            }
+        }
+        for conditional in failed {
+            self.triggers.add(conditional);
+        }
+    }
+
+    /**
+     * removes a resting or pending order and gives back the hold or shares of its unfilled quantity
+     * only the user who placed the order can cancel it
+     */
+    async fn cancel(&mut self, order_id: &str, user_id: &str) -> Result<Order, MarketError> {
+        let order = self
+            .book
+            .get(order_id)
+            .or_else(|| self.triggers.get(order_id).map(|pending| &pending.order))
+            .cloned()
+            .ok_or(MarketError::OrderNotFound)?;
+        if order.user_id != user_id {
+            return Err(MarketError::NotOwner);
+        }
+
+        let db = &self.db;
+        let mut tx = db.pool.begin().await?;
+        release_order(db, &mut tx, &self.opinion_id, &order, order.quantity).await?;
+        db.order.cancel(&mut *tx, &order.id).await?;
+        if self.book.get(order_id).is_some() {
+            let cancelled = Event::Cancelled {
+                order_id: order.id.clone(),
+                quantity: order.quantity,
+                reason: CancelReason::User,
+            };
+            journal::record(db, &mut tx, &self.opinion_id, &[cancelled]).await?;
+        }
+        tx.commit().await?;
+
+        if self.book.remove(order_id).is_none() {
+            self.triggers.remove(order_id);
+        }
+        Ok(order)
+    }
+
+    /**
+     * cancels every resting and pending order of `user_id`, or of everyone without one,
+     * all of them with their released holds in one transaction
+     */
+    async fn cancel_all(
+        &mut self,
+        user_id: Option<&str>,
+        reason: CancelReason,
+    ) -> Result<Vec<Order>, MarketError> {
+        let owned = |order: &&Order| user_id.is_none_or(|user_id| order.user_id == user_id);
+        let resting: Vec<Order> = self
+            .book
+            .against
+            .iter()
+            .chain(self.book.favour.iter())
+            .filter(owned)
+            .cloned()
+            .collect();
+        let pending: Vec<Order> = self
+            .triggers
+            .iter()
+            .map(|pending| &pending.order)
+            .filter(owned)
+            .cloned()
+            .collect();
+        if resting.is_empty() && pending.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let db = &self.db;
+        let mut tx = db.pool.begin().await?;
+        for order in resting.iter().chain(pending.iter()) {
+            release_order(db, &mut tx, &self.opinion_id, order, order.quantity).await?;
+            db.order.cancel(&mut *tx, &order.id).await?;
+        }
+        // pending orders never reached the book, only the resting ones are journaled
+        let events: Vec<Event> = resting
+            .iter()
+            .map(|order| Event::Cancelled {
+                order_id: order.id.clone(),
+                quantity: order.quantity,
+                reason,
+            })
+            .collect();
+        journal::record(db, &mut tx, &self.opinion_id, &events).await?;
+        tx.commit().await?;
+
+        for order in resting.iter() {
+            self.book.remove(&order.id);
+        }
+        for order in pending.iter() {
+            self.triggers.remove(&order.id);
+        }
+        Ok(resting.into_iter().chain(pending).collect())
+    }
+
+    /**
+     * changes price and/or quantity of a resting order and adjusts the hold by the difference
+     * a quantity decrease keeps the queue position, the way replay applies it, a price change or quantity increase
+     * takes the order out and sends it through matching again as if it was new,
+     * it keeps its id, client order id, creation time and self trade prevention mode
+     */
+    async fn amend(
+        &mut self,
+        order_id: &str,
+        user_id: &str,
+        price: Option<Price>,
+        quantity: Option<Quantity>,
+    ) -> Result<Amended, MarketError> {
+        let order = self
+            .book
+            .get(order_id)
+            .cloned()
+            .ok_or(MarketError::OrderNotFound)?;
+        if order.user_id != user_id {
+            return Err(MarketError::NotOwner);
+        }
+
+        let price = price.unwrap_or(order.price);
+        let quantity = quantity.unwrap_or(order.quantity);
+        if price == order.price && quantity == order.quantity {
+            return Ok(Amended::Unchanged(order));
+        }
+
+        // hold only the difference between the new and the old notional,
+        // a sell holds no money but reserves one share per contract
+        let keeps_priority = price == order.price && quantity < order.quantity;
+        let db = &self.db;
+        let mut tx = db.pool.begin().await?;
+        // the amended order has to fit the limits in place of the old one
+        let mut amended = order.clone();
+        amended.price = price;
+        amended.quantity = quantity;
+        let limits = self.limits(&mut tx, &order.user_id).await?;
+        limits
+            .check(
+                db,
+                &mut tx,
+                &self.opinion_id,
+                &self.book,
+                &self.pending(),
+                &amended,
+                true,
+                Some(order_id),
+            )
+            .await?;
+        let held = match order.action {
+            Action::Buy => {
+                let old_hold = order.price.checked_mul(order.quantity)?;
+                let new_hold = price.checked_mul(quantity)?;
+                if new_hold > old_hold {
+                    db.user
+                        .hold_balance(&mut *tx, &order.user_id, new_hold - old_hold)
+                        .await
+                        .map_err(balance_error)
+                } else if new_hold < old_hold {
+                    db.user
+                        .release_balance(&mut *tx, &order.user_id, old_hold - new_hold)
+                        .await
+                        .map_err(MarketError::Db)
+                } else {
+                    Ok(())
+                }
+            }
+            Action::Sell if quantity > order.quantity => db
+                .position
+                .reserve(
+                    &mut *tx,
+                    &self.opinion_id,
+                    &order.user_id,
+                    order.side.as_str(),
+                    quantity - order.quantity,
+                )
+                .await
+                .map_err(shares_error),
+            Action::Sell if quantity < order.quantity => db
+                .position
+                .unreserve(
+                    &mut *tx,
+                    &self.opinion_id,
+                    &order.user_id,
+                    order.side.as_str(),
+                    order.quantity - quantity,
+                )
+                .await
+                .map_err(MarketError::Db),
+            Action::Sell => Ok(()),
+        };
+        held?;
+        db.order
+            .amend(&mut *tx, &order.id, price, quantity, !keeps_priority)
+            .await?;
+        // a requeued order leaves the book completely and is accepted again below
+        let amended = Event::Cancelled {
+            order_id: order.id.clone(),
+            quantity: if keeps_priority {
+                order.quantity - quantity
+            } else {
+                order.quantity
+            },
+            reason: CancelReason::Amended,
+        };
+        journal::record(db, &mut tx, &self.opinion_id, &[amended]).await?;
+
+        if keeps_priority {
+            tx.commit().await?;
+            // only a decrease in size, booked the same way replaying the journal entry does
+            engine::apply(&mut self.book, &[engine::amend_in_place(&order, quantity)]);
+            let resting = self
+                .book
+                .get(order_id)
+                .cloned()
+                .expect("an order amended to a positive quantity stays in the book");
+            return Ok(Amended::InPlace(resting));
+        }
+
+        // goes through matching again, settled in the same transaction as the new hold
+        // the order can't match itself as matching only walks the opposite side
+        // a post only order is checked again, an amend that would make it trade is rejected or repriced
+        let request = CreateOrderDto {
+            quantity,
+            price,
+            side: order.side.clone(),
+            action: order.action,
+            order_type: OrderType::Limit,
+            time_in_force: order.time_in_force,
+            expires_at: order.expires_at,
+            self_trade_prevention: Some(order.self_trade_prevention),
+            client_order_id: order.client_order_id.clone(),
+            trigger: None,
+            post_only: order.post_only,
+            display_quantity: order.iceberg.map(|iceberg| iceberg.display),
+        };
+        let mut taker = request.to_order(order.id, order.user_id);
+        taker.created_at = order.created_at;
+        let placement = self.execute(tx, taker, &request, Some(order_id)).await?;
+        Ok(Amended::Requeued(placement))
+    }
+
+    /**
+     * the book as it is between two commands, nothing else writes the journal of the market
+     * so its last entry is the last one the book contains
+     */
+    async fn take_snapshot(&self) -> Result<MarketSnapshot, MarketError> {
+        let sequence = self.db.journal.last_sequence(&self.opinion_id).await?;
+        Ok(MarketSnapshot {
+            sequence,
+            book: self.book.clone(),
+        })
+    }
+
+    /**
+     * releases the holds of everything still resting or pending and cancels those orders,
+     * the book and the conditional orders are empty afterwards
+     */
+    async fn resolve(&mut self) -> Result<(), MarketError> {
+        let db = &self.db;
+        let mut tx = db.pool.begin().await?;
+        let pending = self.triggers.iter().map(|pending| &pending.order);
+        for order in self
+            .book
+            .against
+            .iter()
+            .chain(self.book.favour.iter())
+            .chain(pending)
+        {
+            release_order(db, &mut tx, &self.opinion_id, order, order.quantity).await?;
+        }
+        db.order
+            .cancel_resting_by_opinion_id(&mut *tx, &self.opinion_id)
+            .await?;
+        journal::record(db, &mut tx, &self.opinion_id, &[Event::Resolved]).await?;
+        tx.commit().await?;
+
+        self.book = OrderBook::empty(self.book.payout);
+        self.triggers.clear();
+        Ok(())
+    }
+
+    /**
+     * books an execution of the engine, the trades with the fills of both orders, the balance effects,
+     * the cancels of self trade prevention and of a remainder that may not rest, and its journal entries
+     * everything goes through `conn`, the caller commits it, the trades are returned in the order they were booked
+     */
+    async fn settle(
+        &self,
+        conn: &mut PgConnection,
+        taker: &Order,
+        execution: &Execution,
+    ) -> Result<Vec<TradeModel>, MarketError> {
+        let db = &self.db;
+        let opinion_id = self.opinion_id.as_str();
+
+        let mut trades = Vec::with_capacity(execution.fills.len());
+        for fill in execution.fills.iter() {
+            // favour user either bought YES or sold NO, against user bought NO or sold YES
+            let (favour, against) = fill.parties(taker);
+            let mut trade = TradeModel::new(
+                None,
+                opinion_id.to_string(),
+                favour.user_id.clone(),
+                against.user_id.clone(),
+                fill.favour_price,
+                fill.against_price,
+                fill.quantity,
+            );
+            trade.favour_action = favour.action.as_str().to_string();
+            trade.against_action = against.action.as_str().to_string();
+            trade.favour_order_id = Some(favour.id.clone());
+            trade.against_order_id = Some(against.id.clone());
+            let taker_side = if favour.id == taker.id {
+                Side::Favour
+            } else {
+                Side::Against
+            };
+            trade.taker_side = Some(taker_side.as_str().to_string());
+            db.trade.create(&mut *conn, &trade).await?;
+            db.order.fill(&mut *conn, &favour.id, fill.quantity).await?;
+            db.order
+                .fill(&mut *conn, &against.id, fill.quantity)
+                .await?;
+            trades.push(trade);
+        }
+
+        for effect in execution.effects.iter() {
+            book_effect(db, &mut *conn, opinion_id, effect).await?;
+        }
+
+        for (resting, cancelled) in execution.self_trade_cancels.iter() {
+            if *cancelled == resting.quantity {
+                db.order.cancel(&mut *conn, &resting.id).await?;
+            } else {
+                db.order
+                    .decrement(&mut *conn, &resting.id, *cancelled)
+                    .await?;
+            }
+        }
+
+        if execution.status == OrderStatus::Cancelled {
+            db.order.cancel(&mut *conn, &taker.id).await?;
+        } else if !execution.cancelled.is_zero() {
+            db.order
+                .decrement(&mut *conn, &taker.id, execution.cancelled)
+                .await?;
+        }
+
+        let events = journal::execution_events(taker, execution);
+        journal::record(db, conn, opinion_id, &events).await?;
+        Ok(trades)
+    }
+}
+
+/**
+ * holds the notional of a new buy order, or reserves the shares a sell order offers,
+ * and records it as open, both through `conn` so an order never exists without its hold
+ * a conditional order is recorded as pending with its trigger, the hold is taken all the same
+ * so it can't fail for lack of money once it fires
+ */
+async fn hold_and_record_order(
+    db: &DB,
+    conn: &mut PgConnection,
+    order_id: &str,
+    user_id: &String,
+    opinion_id: &str,
+    order: &CreateOrderDto,
+) -> Result<(), MarketError> {
+    match order.action {
+        Action::Buy => {
+            let hold = order.price.checked_mul(order.quantity)?;
+            db.user
+                .hold_balance(&mut *conn, user_id, hold)
+                .await
+                .map_err(balance_error)?
+        }
+        Action::Sell => db
+            .position
+            .reserve(
+                &mut *conn,
+                opinion_id,
+                user_id,
+                order.side.as_str(),
+                order.quantity,
+            )
+            .await
+            .map_err(shares_error)?,
+    }
+    let status = match order.trigger {
+        Some(_) => OrderStatus::Pending,
+        None => OrderStatus::Open,
+    };
+    record_order(db, &mut *conn, order_id, user_id, opinion_id, order, status).await?;
+    if let Some(trigger) = &order.trigger {
+        let stp = order.self_trade_prevention.unwrap_or_default();
+        db.order
+            .create_conditional(&mut *conn, order_id, trigger, stp.as_str())
+            .await?;
+    }
+    Ok(())
+}
+
+/// a hold the balance doesn't cover breaks the check that keeps it from going below zero
+fn balance_error(err: sqlx::Error) -> MarketError {
+    match err {
+        sqlx::Error::Database(db_err) if db_err.is_check_violation() => {
+            MarketError::InsufficientBalance
+        }
+        err => MarketError::Db(err),
+    }
+}
+
+/// `reserve` finds no position with enough free shares
+fn shares_error(err: sqlx::Error) -> MarketError {
+    match err {
+        sqlx::Error::RowNotFound => MarketError::InsufficientShares,
+        err => MarketError::Db(err),
+    }
+}
+
+/// the row of a new order with `status`
+async fn record_order<'a, E>(
+    db: &DB,
+    executor: E,
+    order_id: &str,
+    user_id: &str,
+    opinion_id: &str,
+    order: &CreateOrderDto,
+    status: OrderStatus,
+) -> Result<(), sqlx::Error>
+where
+    E: sqlx::Executor<'a, Database = Postgres>,
+{
+    db.order
+        .create(
+            executor,
+            &OrderModel {
+                id: order_id.to_string(),
+                opinion_id: opinion_id.to_string(),
+                user_id: user_id.to_string(),
+                side: order.side.as_str().to_string(),
+                price: order.price,
+                quantity: order.quantity,
+                remaining_quantity: order.quantity,
+                status: status.as_str().to_string(),
+                order_type: order.order_type.as_str().to_string(),
+                time_in_force: order.time_in_force.as_str().to_string(),
+                expires_at: order.expires_at,
+                action: order.action.as_str().to_string(),
+                client_order_id: order.client_order_id.clone(),
+                display_quantity: order.display_quantity,
+                created_at: Utc::now(),
+                updated_at: Utc::now(),
+            },
+        )
+        .await
+}
+
+/// gives back what an order set aside for `quantity` of it, the hold of a buy or the reserved shares of a sell
+pub async fn release_order(
+    db: &DB,
+    conn: &mut PgConnection,
+    opinion_id: &str,
+    order: &Order,
+    quantity: Quantity,
+) -> Result<(), MarketError> {
+    let effect = engine::release(order, quantity)?;
+    book_effect(db, conn, opinion_id, &effect).await
+}
+
+/// writes one balance effect of the engine to the users and positions of the market
+async fn book_effect(
+    db: &DB,
+    conn: &mut PgConnection,
+    opinion_id: &str,
+    effect: &BalanceEffect,
+) -> Result<(), MarketError> {
+    match effect {
+        BalanceEffect::Release { user_id, amount } => {
+            db.user.release_balance(conn, user_id, *amount).await?;
+        }
+        BalanceEffect::Unreserve {
+            user_id,
+            side,
+            quantity,
+        } => {
+            db.position
+                .unreserve(conn, opinion_id, user_id, side.as_str(), *quantity)
+                .await?;
+        }
+        BalanceEffect::SettleHold {
+            user_id,
+            hold,
+            payout,
+        } => {
+            db.user.settle_hold(conn, user_id, *hold, *payout).await?;
+        }
+        BalanceEffect::OpenPosition {
+            user_id,
+            side,
+            quantity,
+            locked,
+            cost,
+        } => {
+            db.position
+                .open(
+                    conn,
+                    opinion_id,
+                    user_id,
+                    side.as_str(),
+                    *quantity,
+                    *locked,
+                    *cost,
+                )
+                .await?;
+        }
+        BalanceEffect::ClosePosition {
+            user_id,
+            side,
+            quantity,
+            proceeds,
+        } => {
+            let released = db
+                .position
+                .close(&mut *conn, opinion_id, user_id, side.as_str(), *quantity)
+                .await?;
+            db.user
+                .settle_hold(&mut *conn, user_id, released, *proceeds)
+                .await?;
+        }
+    }
+    Ok(())
+}
@@ -20,7 +20,7 @@ pub async fn auth_middleware(mut request:Request, next:Next)-> impl IntoResponse
     let mut validation= Validation::new(Algorithm::HS256);
     validation.required_spec_claims.clear();
     validation.validate_exp=false;
-    let data = decode::<UserModel>(&token, &DecodingKey::from_secret("secret".as_ref()), &validation);
+    let data = decode::<UserModel>(token, &DecodingKey::from_secret("secret".as_ref()), &validation);
     let user =match data {
         Ok(data)=>{data.claims},
         Err(err)=>{
@@ -0,0 +1,156 @@
+use std::{
+    fmt,
+    ops::{Add, AddAssign, Sub, SubAssign},
+};
+
+use serde::{Deserialize, Serialize};
+use validator::ValidateRange;
+
+/// an amount went past what 64 bits can hold, or below zero where it has to stay positive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overflow;
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "amount out of range")
+    }
+}
+
+impl std::error::Error for Overflow {}
+
+/**
+ * integer newtypes so a price can't end up where a quantity or an amount of money belongs
+ * all of them are plain numbers in json and BIGINT in the database
+ * `checked_*` report an overflow, the operators panic on one instead of wrapping around
+ * so they are only used where the values are bounded already (matching inside a validated order)
+ */
+macro_rules! integer_newtype {
+    ($name:ident) => {
+        #[derive(
+            Debug,
+            Clone,
+            Copy,
+            PartialEq,
+            Eq,
+            PartialOrd,
+            Ord,
+            Hash,
+            Default,
+            Serialize,
+            Deserialize,
+            sqlx::Type,
+        )]
+        #[serde(transparent)]
+        #[sqlx(transparent)]
+        pub struct $name(i64);
+
+        impl $name {
+            pub const ZERO: Self = Self(0);
+
+            pub const fn new(value: i64) -> Self {
+                Self(value)
+            }
+
+            pub fn get(self) -> i64 {
+                self.0
+            }
+
+            pub fn is_zero(self) -> bool {
+                self.0 == 0
+            }
+
+            pub fn checked_add(self, other: Self) -> Result<Self, Overflow> {
+                self.0.checked_add(other.0).map(Self).ok_or(Overflow)
+            }
+
+            /// fails below zero as well, none of these can be negative
+            pub fn checked_sub(self, other: Self) -> Result<Self, Overflow> {
+                match self.0.checked_sub(other.0) {
+                    Some(value) if value >= 0 => Ok(Self(value)),
+                    _ => Err(Overflow),
+                }
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+
+            fn add(self, other: Self) -> Self {
+                self.checked_add(other)
+                    .expect(concat!(stringify!($name), " overflow"))
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, other: Self) {
+                *self = *self + other;
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+
+            fn sub(self, other: Self) -> Self {
+                self.checked_sub(other)
+                    .expect(concat!(stringify!($name), " overflow"))
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, other: Self) {
+                *self = *self - other;
+            }
+        }
+
+        impl From<i64> for $name {
+            fn from(value: i64) -> Self {
+                Self(value)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        // lets `#[validate(range(...))]` take bounds of the same type
+        impl ValidateRange<$name> for $name {
+            fn greater_than(&self, max: $name) -> Option<bool> {
+                Some(*self > max)
+            }
+
+            fn less_than(&self, min: $name) -> Option<bool> {
+                Some(*self < min)
+            }
+        }
+
+        impl ValidateRange<$name> for Option<$name> {
+            fn greater_than(&self, max: $name) -> Option<bool> {
+                self.map(|value| value > max)
+            }
+
+            fn less_than(&self, min: $name) -> Option<bool> {
+                self.map(|value| value < min)
+            }
+        }
+    };
+}
+
+integer_newtype!(Price);
+integer_newtype!(Quantity);
+integer_newtype!(Money);
+
+impl Price {
+    /// price of the other outcome, prices of a YES and a NO share add up to the payout of the market
+    /// selling YES at 700 is the same as buying NO at 300 when a share pays 1000
+    /// prices are always between zero and the payout, so this can't go out of range
+    pub fn complement(self, payout: Price) -> Price {
+        payout - self
+    }
+
+    /// what `quantity` shares cost at this price
+    pub fn checked_mul(self, quantity: Quantity) -> Result<Money, Overflow> {
+        self.0.checked_mul(quantity.0).map(Money).ok_or(Overflow)
+    }
+}
@@ -87,5 +87,5 @@ pub async fn create_user(
 
 #[axum::debug_handler]
 pub async fn active_user(Extension(user): Extension<UserModel>) -> impl IntoResponse {
-    return Json(user);
+    Json(user)
 }
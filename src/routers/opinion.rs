@@ -1,19 +1,24 @@
 use axum::{
-    Json, Router,
-    extract::{Path, State},
+    Extension, Json, Router,
+    extract::{Path, Query, State},
     http::StatusCode,
     middleware,
     response::IntoResponse,
     routing::{get, post},
 };
+use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use sqlx::prelude::FromRow;
 
 use crate::{
-    db::{db::DB, opinion::OpinionModel, trade::TradeModel},
+    amm::{Lmsr, MarketMaker},
+    conditional::Triggers,
+    db::{db::DB, opinion::OpinionModel, user::UserModel},
+    market::{MarketError, spawn_market},
     middlewares::auth::auth_middleware,
-    state::{AppState, OrderBook},
+    money::{Money, Price, Quantity},
+    state::{AppState, MarketParams, OrderBook, Side, depth_levels},
 };
 
 #[derive(Debug, Serialize, Deserialize, FromRow)]
@@ -23,8 +28,10 @@ pub struct MarketModel {
     pub question: String,
     pub description: Option<String>,
     pub result: Option<bool>,
-    pub yes_price: i32,
-    pub no_price: i32,
+    pub yes_price: Price,
+    pub no_price: Price,
+    #[serde(flatten)]
+    pub params: MarketParams,
 }
 
 pub fn opinion_router() -> Router<AppState> {
@@ -33,126 +40,130 @@ pub fn opinion_router() -> Router<AppState> {
         .route("/markets", get(get_opinions))
         .route("/{opinion_id}", get(get_opinion_by_id))
         .route("/depth/{opinion_id}", get(get_market_depth_by_id))
+        .route("/quote/{opinion_id}", get(get_quote))
         .route("/{opinion_id)}/declare-result", post(declare_result))
         .layer(middleware::from_fn(auth_middleware))
 }
 
+/// a new market, pricing and limit fields that are left out get the defaults of `MarketParams`
 #[derive(Serialize, Deserialize)]
-struct DeclareResultDto {
-    result: bool,
+pub struct CreateOpinionDto {
+    pub question: String,
+    pub description: Option<String>,
+    #[serde(flatten)]
+    pub params: MarketParams,
 }
 
-async fn release_all_balances(db: &DB, orders: &OrderBook) -> bool {
-    let mut tx = match db.pool.begin().await {
-        Ok(tx) => tx,
-        Err(_) => return false,
-    };
+#[derive(Deserialize)]
+struct QuoteQuery {
+    side: Side,
+    quantity: Quantity,
+}
 
-    for order in orders.against.iter() {
-        if db
-            .user
-            .release_balance(&mut *tx, &order.user_id, order.price * order.quantity)
-            .await
-            .is_err()
-        {
-            return false;
-        }
-    }
+#[derive(Serialize, Deserialize)]
+struct DeclareResultDto {
+    result: bool,
+}
 
-    for order in orders.favour.iter() {
-        if db
-            .user
-            .release_balance(&mut *tx, &order.user_id, order.price * order.quantity)
-            .await
-            .is_err()
-        {
-            return false;
-        };
-    }
-    if tx.commit().await.is_err() {
-        return false;
-    };
-    return true;
+/// why the prize of a market was not paid out
+enum PrizeError {
+    /// the market got its result earlier and was paid out then
+    AlreadyResolved,
+    Failed,
 }
 
+/**
+ * pays whoever holds the shares at resolution, a winning share is worth the full payout of the market
+ * the collateral locked in every position is spent either way
+ * the result is set first and only if the market has none, a second declaration finds it set and pays nothing
+ */
 async fn distribute_prize(
     db: &DB,
-    trades: Vec<TradeModel>,
     opinion_id: &String,
+    payout: Price,
     result: bool,
-) -> bool {
+) -> Result<(), PrizeError> {
     let mut tx = match db.pool.begin().await {
         Ok(tx) => tx,
-        Err(_) => return false,
+        Err(_) => return Err(PrizeError::Failed),
     };
 
-    // if result is true then add amount to favour users and deduct from against
+    // the update locks the opinion, a declaration running at the same time waits for this one to commit
+    match db.opinion.update_result(&mut *tx, opinion_id, result).await {
+        Ok(true) => {}
+        Ok(false) => return Err(PrizeError::AlreadyResolved),
+        Err(e) => {
+            println!("Error while setting the result: {:?}", e);
+            return Err(PrizeError::Failed);
+        }
+    }
 
-    if result {
-        // favour user is winner
-        let mut c = 0;
-        for trade in trades.iter() {
-            let update_result = db
-                .user
-                .update_balance_post_result(
-                    &mut *tx,
-                    &trade.favour_user_id,
-                    &trade.against_user_id,
-                    trade.favour_price * trade.quantity,
-                    trade.against_price * trade.quantity,
-                    (trade.favour_price + trade.against_price) * trade.quantity,
-                )
-                .await;
-            //     .is_err()
-            // {
-            //     println!("error in wiining favour user");
-            //     return false;
-            // };
-            c += 1;
+    let positions = match db.position.find_by_opinion_id(&mut *tx, opinion_id).await {
+        Ok(positions) => positions,
+        Err(e) => {
+            println!("Error while getting positions: {:?}", e);
+            return Err(PrizeError::Failed);
+        }
+    };
 
-            if let Err(e) = update_result {
-                println!("Error in winning favour user: {:?}", e);
-                println!("{}", c);
-                println!("{:?}", trade);
-                return false;
+    // if result is true then favour (YES) shares win
+    let winner = if result { Side::Favour } else { Side::Against };
+    for position in positions.iter() {
+        let payout = if position.side == winner.as_str() {
+            match payout.checked_mul(position.quantity) {
+                Ok(payout) => payout,
+                Err(e) => {
+                    println!("Error in settling position: {:?}", e);
+                    println!("{:?}", position);
+                    return Err(PrizeError::Failed);
+                }
             }
+        } else {
+            Money::ZERO
+        };
+        if let Err(e) = db
+            .user
+            .settle_hold(&mut *tx, &position.user_id, position.locked, payout)
+            .await
+        {
+            println!("Error in settling position: {:?}", e);
+            println!("{:?}", position);
+            return Err(PrizeError::Failed);
         }
-    } else {
-        // against user is winner
-        for trade in trades.iter() {
-            if db
+    }
+
+    // the maker owner gets back its funding with what buyers paid, less what its winning shares pay out
+    match db.amm.find_one(&mut *tx, opinion_id).await {
+        Ok(Some(pool)) => {
+            let refund = pool.refund(payout, &winner).unwrap_or_else(|e| {
+                println!(
+                    "Market maker paid out more than it was funded with: {:?}",
+                    e
+                );
+                println!("{:?}", pool);
+                Money::ZERO
+            });
+            if let Err(e) = db
                 .user
-                .update_balance_post_result(
-                    &mut *tx,
-                    &trade.against_user_id,
-                    &trade.favour_user_id,
-                    trade.against_price * trade.quantity,
-                    trade.favour_price * trade.quantity,
-                    (trade.favour_price + trade.against_price) * trade.quantity,
-                )
+                .settle_hold(&mut *tx, &pool.owner_id, pool.funding, refund)
                 .await
-                .is_err()
             {
-                println!("error in wiining against user");
-                return false;
-            };
+                println!("Error in settling market maker: {:?}", e);
+                return Err(PrizeError::Failed);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            println!("Error while getting market maker: {:?}", e);
+            return Err(PrizeError::Failed);
         }
-    }
-
-    if db
-        .opinion
-        .update_result(&mut *tx, opinion_id, result)
-        .await
-        .is_err()
-    {
-        return false;
     }
 
     if tx.commit().await.is_err() {
-        return false;
+        return Err(PrizeError::Failed);
     }
 
-    return true;
+    Ok(())
 }
 
 async fn declare_result(
@@ -161,53 +172,68 @@ async fn declare_result(
     Json(declare_result_dto): Json<DeclareResultDto>,
 ) -> impl IntoResponse {
     //release the hold money from state
-    let db = state.db;
-
-    let orders = {
-        let read_guard = state.order_book.read().await;
-        read_guard.get(&opinion_id).cloned()
-    };
-
-    if let Some(orders) = orders {
-        if release_all_balances(&db, &orders).await {
-            let mut state2 = state.order_book.write().await;
-            state2.remove(&opinion_id);
-        }
-    }
-
-    // get the trades
-    let trades = match db.trade.get_trades_by_opinion_id(&opinion_id).await {
-        Ok(trades) => trades,
+    let db = state.db.clone();
+    let payout = match db.opinion.find_one(opinion_id.clone()).await {
+        Ok(opinion) => opinion.payout,
         Err(_) => {
             return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({"message":"Error while getting trades list for opinion"})),
+                StatusCode::NOT_FOUND,
+                Json(json!({"message":"Market not found"})),
             )
                 .into_response();
         }
     };
 
-    // distribute the prize
+    // the market releases everything still resting and stops, no order can reach it afterwards
+    // nothing is paid out while it still runs with orders in its book
+    if let Some(market) = state.market(&opinion_id).await {
+        match market.resolve().await {
+            // closed means it was resolved by a declaration running at the same time
+            Ok(()) | Err(MarketError::Closed) => {
+                state.markets.write().await.remove(&opinion_id);
+            }
+            Err(err) => {
+                eprintln!("Error while resolving market {}: {:?}", opinion_id, err);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(
+                        json!({"message":"Error while resolving the market, nothing was paid out"}),
+                    ),
+                )
+                    .into_response();
+            }
+        }
+    }
 
-    let status = distribute_prize(&db, trades, &opinion_id, declare_result_dto.result).await;
-    if !status {
-        return (
+    // distribute the prize to the current holders of the shares
+    match distribute_prize(&db, &opinion_id, payout, declare_result_dto.result).await {
+        Ok(()) => Json(json!({"message":"Successfully distributed the prize"})).into_response(),
+        Err(PrizeError::AlreadyResolved) => (
+            StatusCode::CONFLICT,
+            Json(json!({"message":"Market already has a result"})),
+        )
+            .into_response(),
+        Err(PrizeError::Failed) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({"message":"Error while prize distribution"})),
         )
-            .into_response();
+            .into_response(),
     }
+}
 
-    Json(json!({"message":"Successfully distributed the prize"})).into_response()
+#[derive(Deserialize)]
+struct DepthQuery {
+    levels: Option<usize>,
 }
 
+/// best price levels of both sides with the implied probabilities, nothing about who placed the orders
 async fn get_market_depth_by_id(
     State(state): State<AppState>,
     Path(opinion_id): Path<String>,
+    Query(query): Query<DepthQuery>,
 ) -> impl IntoResponse {
-    let order_book = state.order_book.read().await;
-    let orders = match order_book.get(&opinion_id) {
-        Some(orders) => orders,
+    let book = match state.market(&opinion_id).await {
+        Some(market) => market.book(),
         None => {
             return (
                 StatusCode::NOT_FOUND,
@@ -216,15 +242,104 @@ async fn get_market_depth_by_id(
                 .into_response();
         }
     };
-    return Json(json!({"order_book":orders.clone()})).into_response();
+    Json(json!({"depth":book.depth(depth_levels(query.levels), Utc::now())})).into_response()
 }
 
+/// what buying `quantity` shares of `side` from the market maker would cost and how far it moves the price
+async fn get_quote(
+    State(state): State<AppState>,
+    Path(opinion_id): Path<String>,
+    Query(query): Query<QuoteQuery>,
+) -> impl IntoResponse {
+    let Some(market) = state.market(&opinion_id).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({"message":"Market not found"})),
+        )
+            .into_response();
+    };
+    let Some(lmsr) = market.maker() else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"message":"Market has no market maker"})),
+        )
+            .into_response();
+    };
+    if query.quantity <= Quantity::ZERO {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"message":"Quantity must be positive"})),
+        )
+            .into_response();
+    }
+    match lmsr.quote(&query.side, query.quantity, market.params()) {
+        Ok(quote) => Json(json!({ "quote": quote })).into_response(),
+        Err(_) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"message":"Quantity is too large"})),
+        )
+            .into_response(),
+    }
+}
+
+/**
+ * a market with a market maker is funded by its creator, the most the maker can lose is held
+ * from their balance until the market resolves
+ */
 pub async fn create_opinion(
     State(app_state): State<AppState>,
-    Json(opinion): Json<OpinionModel>,
+    Extension(user): Extension<UserModel>,
+    Json(opinion): Json<CreateOpinionDto>,
 ) -> impl IntoResponse {
-    let db = app_state.db;
-    let opinion = db.opinion.insert(opinion.question).await;
+    let params = opinion.params;
+    if let Err(message) = params.check() {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "message": message }))).into_response();
+    }
+    let funding = match params
+        .liquidity
+        .map(|liquidity| Lmsr::funding(liquidity, params.payout))
+        .transpose()
+    {
+        Ok(funding) => funding,
+        Err(_) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"message":"Liquidity is too large"})),
+            )
+                .into_response();
+        }
+    };
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    let db = app_state.db.clone();
+    let mut tx = match db.pool.begin().await {
+        Ok(tx) => tx,
+        Err(err) => {
+            println!("{:?}", err);
+            return Json("Error Occurred while creating opinion").into_response();
+        }
+    };
+    let opinion = db
+        .opinion
+        .insert(
+            &mut *tx,
+            &OpinionModel {
+                id: None,
+                question: opinion.question,
+                description: opinion.description,
+                result: None,
+                payout: params.payout,
+                tick_size: params.tick_size,
+                min_price: params.min_price,
+                max_price: params.max_price,
+                max_order_size: params.limits.max_order_size,
+                max_open_orders: params.limits.max_open_orders,
+                max_position: params.limits.max_position,
+                max_notional: params.limits.max_notional,
+                trading_mode: params.trading_mode.as_str().to_string(),
+                liquidity: params.liquidity,
+            },
+        )
+        .await;
 
     let opinion = match opinion {
         Result::Ok(opinion) => opinion,
@@ -234,8 +349,44 @@ pub async fn create_opinion(
         }
     };
 
-    let mut order_book = app_state.order_book.write().await;
-    order_book.insert(opinion.id.clone().unwrap(), OrderBook::empty());
+    let opinion_id = opinion.id.clone().unwrap();
+    let mut maker = None;
+    if let (Some(funding), Some(liquidity)) = (funding, params.liquidity) {
+        if let Err(err) = db.user.hold_balance(&mut *tx, &user_id, funding).await {
+            eprintln!("DB error while holding market maker funding: {:?}", err);
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"message":format!("Insufficient balance, the market maker needs a funding of {}", funding)})),
+            )
+                .into_response();
+        }
+        if let Err(err) = db
+            .amm
+            .create(&mut *tx, &opinion_id, &user_id, funding)
+            .await
+        {
+            println!("{:?}", err);
+            return Json("Error Occurred while creating opinion").into_response();
+        }
+        maker = Some(MarketMaker {
+            owner_id: user_id,
+            lmsr: Lmsr::new(liquidity),
+        });
+    }
+    if let Err(err) = tx.commit().await {
+        println!("{:?}", err);
+        return Json("Error Occurred while creating opinion").into_response();
+    }
+
+    let market = spawn_market(
+        db.clone(),
+        opinion_id.clone(),
+        params,
+        OrderBook::empty(params.payout),
+        Triggers::default(),
+        maker,
+    );
+    app_state.markets.write().await.insert(opinion_id, market);
 
     Json(json!(opinion)).into_response()
 }
@@ -247,21 +398,42 @@ pub async fn get_opinions(State(app_state): State<AppState>) -> impl IntoRespons
         Ok(opinions) => opinions,
         Err(_) => return Json("Error occurred while fetching opinions").into_response(),
     };
-    let order_book = app_state.order_book.read().await;
+    let order_book = app_state.markets.read().await;
     let mut markets: Vec<MarketModel> = vec![];
+    // expired orders still waiting for the sweeper don't make a price
+    let now = Utc::now();
     for op in opinions.iter() {
         let id = match &op.id {
             Some(id) => id,
             None => continue,
         };
-        let orders = match order_book.get(id) {
-            Some(orders) => orders,
+        let market = match order_book.get(id) {
+            Some(market) => market,
             None => continue,
         };
+        let orders = market.book();
 
-        // highest price in NO will be the best price (best Yes = 1000 - highest NO = Lowest Yes) for yes to buy and visa versa
-        let yes_price = orders.against.last().map(|o| 1000 - o.price).unwrap_or(0) as i32;
-        let no_price = orders.favour.last().map(|o| 1000 - o.price).unwrap_or(0) as i32;
+        // the maker quotes both outcomes, rounded to the nearest whole price
+        let maker_price =
+            |lmsr: Lmsr, side: Side| Price::new(lmsr.price(&side, op.payout).round() as i64);
+        // highest price in NO will be the best price (best Yes = payout - highest NO = Lowest Yes) for yes to buy and visa versa
+        let yes_price = orders
+            .against
+            .touch(now)
+            .map(|price| price.complement(op.payout))
+            .unwrap_or(Price::ZERO);
+        let no_price = orders
+            .favour
+            .touch(now)
+            .map(|price| price.complement(op.payout))
+            .unwrap_or(Price::ZERO);
+        let (yes_price, no_price) = match market.maker() {
+            Some(lmsr) => (
+                maker_price(lmsr, Side::Favour),
+                maker_price(lmsr, Side::Against),
+            ),
+            None => (yes_price, no_price),
+        };
         let market = MarketModel {
             id: id.clone(),
             question: op.question.clone(),
@@ -269,11 +441,12 @@ pub async fn get_opinions(State(app_state): State<AppState>) -> impl IntoRespons
             result: op.result,
             yes_price,
             no_price,
+            params: MarketParams::from(op),
         };
         markets.push(market);
     }
 
-    return Json(markets).into_response();
+    Json(markets).into_response()
 }
 
 pub async fn get_opinion_by_id(
@@ -1,41 +1,353 @@
 use axum::{
     Extension, Json, Router,
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
     middleware::from_fn,
-    response::IntoResponse,
-    routing::{get, post},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
 };
-use serde_json::json;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
 use validator::Validate;
 
 use crate::{
-    db::{db::DB, trade::TradeModel, user::UserModel},
+    amm::TradingMode,
+    db::{
+        db::DB,
+        order::{ClientOrderModel, OrderStatus},
+        user::UserModel,
+    },
+    journal::CancelReason,
+    market::{Amended, MarketError, MarketHandle},
     middlewares::auth::auth_middleware,
-    state::{AppState, CreateOrderDto, Order, OrderBook, Side},
+    money::Quantity,
+    state::{
+        Action, AmendOrderDto, AppState, CreateOrderDto, MarketParams, Order, OrderType,
+        SelfTradePrevention, depth_levels,
+    },
 };
 
+/// most orders a single batch may hold
+const MAX_BATCH_SIZE: usize = 20;
+
+/// header that sets the client order id, for clients that retry on the HTTP level
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
 pub fn order_router() -> Router<AppState> {
     Router::new()
         .route("/{opinion_id}", post(handle_order))
+        .route("/{opinion_id}/batch", post(handle_batch))
+        .route("/", delete(cancel_all_orders))
+        .route("/admin", delete(admin_cancel_orders))
         .route("/order_book", get(get_order_book))
+        .route("/history", get(get_order_history))
+        .route("/conditional", get(get_conditional_orders))
+        .route(
+            "/client/{client_order_id}",
+            get(get_order_by_client_id).delete(cancel_order_by_client_id),
+        )
+        .route(
+            "/{opinion_id}/{order_id}",
+            delete(cancel_order).patch(amend_order),
+        )
         .layer(from_fn(auth_middleware))
 }
 
-async fn get_order_book(State(state): State<AppState>) -> impl IntoResponse {
-    let order_book = state.order_book.read().await;
-    return Json(json!({"order_book":order_book.clone()})).into_response();
+#[derive(Deserialize)]
+pub struct OrderBookQuery {
+    opinion_id: String,
+    levels: Option<usize>,
+}
+
+/// aggregated book of one market, the same as `/market/depth/{opinion_id}`
+async fn get_order_book(
+    State(state): State<AppState>,
+    Query(query): Query<OrderBookQuery>,
+) -> impl IntoResponse {
+    match state.market(&query.opinion_id).await {
+        Some(market) => {
+            let depth = market.book().depth(depth_levels(query.levels), Utc::now());
+            Json(json!({ "depth": depth })).into_response()
+        }
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"message":"Order Book not found"})),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct OrderHistoryQuery {
+    /// opinion id of the market
+    market: Option<String>,
+    status: Option<OrderStatus>,
+    /// only orders sent at or after this time
+    from: Option<DateTime<Utc>>,
+    /// only orders sent before this time
+    to: Option<DateTime<Utc>>,
+}
+
+/// every order the user sent and what became of it, newest first
+async fn get_order_history(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserModel>,
+    Query(query): Query<OrderHistoryQuery>,
+) -> impl IntoResponse {
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    let history = state
+        .db
+        .order
+        .find_history(
+            &user_id,
+            query.market.as_deref(),
+            None,
+            query.status.map(|status| status.as_str()),
+            query.from,
+            query.to,
+        )
+        .await;
+    match history {
+        Ok(orders) => Json(json!({ "orders": orders })).into_response(),
+        Err(err) => {
+            eprintln!("DB error while reading order history: {:?}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message":"Error while reading order history"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ConditionalOrdersQuery {
+    /// opinion id of the market
+    market: Option<String>,
 }
 
-async fn hold_balance(db: &DB, user_id: &String, order: &CreateOrderDto) -> bool {
-    let result = db
-        .user
-        .hold_balance(user_id, (&order.price) * (&order.quantity))
+/**
+ * conditional orders of the user that wait for their trigger, oldest first
+ * they are cancelled like any other order, by order id or client order id
+ */
+async fn get_conditional_orders(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserModel>,
+    Query(query): Query<ConditionalOrdersQuery>,
+) -> impl IntoResponse {
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    let pending = state
+        .db
+        .order
+        .find_pending_conditionals(Some(&user_id), query.market.as_deref())
         .await;
+    match pending {
+        Ok(orders) => Json(json!({ "orders": orders })).into_response(),
+        Err(err) => {
+            eprintln!("DB error while reading conditional orders: {:?}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message":"Error while reading conditional orders"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// one order of the user with its history, found by the id the user gave it
+async fn get_order_by_client_id(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserModel>,
+    Path(client_order_id): Path<String>,
+) -> impl IntoResponse {
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    let history = state
+        .db
+        .order
+        .find_history(&user_id, None, Some(&client_order_id), None, None, None)
+        .await;
+    match history.map(|orders| orders.into_iter().next()) {
+        Ok(Some(order)) => Json(json!({ "order": order })).into_response(),
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"message":"Order not found"})),
+        )
+            .into_response(),
+        Err(err) => {
+            eprintln!("DB error while reading order: {:?}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message":"Error while reading order"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/**
+ * fills in the price of a market order and checks the order against the rules of the market
+ * returns the body of the error response if it breaks one
+ */
+fn check_order(params: &MarketParams, order: &mut CreateOrderDto) -> Result<(), Value> {
+    if params.trading_mode == TradingMode::Amm && order.action == Action::Sell {
+        return Err(
+            json!({"message":"The market maker of this market only sells, buy the other outcome instead"}),
+        );
+    }
+    if order.order_type == OrderType::Market {
+        order.price = params.market_order_price(order.action);
+    }
+    if let Err(e) = order.validate() {
+        return Err(json!({ "message": "Trade failed","errors":e }));
+    }
+    if !params.accepts(order.price) {
+        return Err(json!({ "message": params.price_error() }));
+    }
+    if let Some(trigger) = order.trigger
+        && (trigger.price.is_zero() || trigger.price >= params.payout)
+    {
+        return Err(
+            json!({"message":format!("Trigger price must be above 0 and below the payout of {}", params.payout)}),
+        );
+    }
+    if order.post_only.is_some()
+        && (!order.rests_in_book()
+            || order.trigger.is_some()
+            || params.trading_mode == TradingMode::Amm)
+    {
+        return Err(
+            json!({"message":"post_only is only allowed for good till cancelled limit orders without a trigger in markets with an order book"}),
+        );
+    }
+    if let Some(display_quantity) = order.display_quantity
+        && (display_quantity <= Quantity::ZERO
+            || display_quantity >= order.quantity
+            || !order.rests_in_book()
+            || order.trigger.is_some()
+            || params.trading_mode == TradingMode::Amm)
+    {
+        return Err(
+            json!({"message":"display_quantity has to be positive and below the quantity, and is only allowed for good till cancelled limit orders without a trigger in markets with an order book"}),
+        );
+    }
+    if let Some(expires_at) = order.expires_at
+        && (!order.rests_in_book() || order.trigger.is_some() || expires_at <= Utc::now())
+    {
+        return Err(
+            json!({"message":"expires_at has to be in the future and is only allowed for good till cancelled limit orders without a trigger"}),
+        );
+    }
+    Ok(())
+}
+
+/**
+ * status and message for an order the market refused, the same for single orders, batches and amends
+ * `None` if it failed on our side, that is answered with a 500 by the caller
+ */
+fn order_error(err: &MarketError) -> Option<(StatusCode, String)> {
+    let (status, message) = match err {
+        MarketError::Closed => (StatusCode::NOT_FOUND, "Market is closed"),
+        MarketError::WouldTake => (
+            StatusCode::CONFLICT,
+            "Post only order would trade with a resting order, it was not placed",
+        ),
+        MarketError::InsufficientBalance => (
+            StatusCode::BAD_REQUEST,
+            "You cannot trade with amount more than your balance",
+        ),
+        MarketError::InsufficientShares => (
+            StatusCode::BAD_REQUEST,
+            "You cannot sell more shares than you hold",
+        ),
+        MarketError::LimitExceeded(message) => {
+            return Some((StatusCode::BAD_REQUEST, message.clone()));
+        }
+        MarketError::Rejected { error, .. } => return order_error(error),
+        _ => return None,
+    };
+    Some((status, message.to_string()))
+}
+
+/// status and message for an order the market didn't take, its hold is released already
+fn placement_error(err: &MarketError) -> (StatusCode, String) {
+    order_error(err).unwrap_or_else(|| {
+        eprintln!("Error while placing order: {:?}", err);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Order could not be placed, nothing was traded".to_string(),
+        )
+    })
+}
+
+/// takes the client order id from the `Idempotency-Key` header if the body has none, both have to agree if both are given
+fn take_idempotency_key(
+    headers: &HeaderMap,
+    order: &mut CreateOrderDto,
+) -> Result<(), &'static str> {
+    let key = match headers.get(IDEMPOTENCY_KEY) {
+        Some(key) => key
+            .to_str()
+            .map_err(|_| "Idempotency-Key has to be plain text")?,
+        None => return Ok(()),
+    };
+    match &order.client_order_id {
+        Some(client_order_id) if client_order_id != key => {
+            Err("client_order_id and the Idempotency-Key header differ")
+        }
+        Some(_) => Ok(()),
+        None => {
+            order.client_order_id = Some(key.to_string());
+            Ok(())
+        }
+    }
+}
 
-    match result {
-        Ok(_) => true,
-        Err(_) => false,
+/**
+ * the response the request that placed the order got, rebuilt from the order as it is now
+ * if it couldn't be stored, the market only commits the order together with its placement
+ */
+fn replay(order: ClientOrderModel) -> Response {
+    match (order.response_code, order.response) {
+        (Some(code), Some(body)) => {
+            let status = StatusCode::from_u16(code as u16).unwrap_or(StatusCode::OK);
+            let body: Value = serde_json::from_str(&body).unwrap_or(Value::Null);
+            (status, Json(body)).into_response()
+        }
+        _ => Json(json!({"message":"ok","order_id":order.id,"client_order_id":order.client_order_id,"status":order.status}))
+            .into_response(),
+    }
+}
+
+/// keeps the response to a request that placed an order with a client order id, a retry gets it again
+async fn remember_response(db: &DB, order_id: &String, status: StatusCode, body: &Value) {
+    if let Err(err) = db
+        .order
+        .save_response(order_id, status.as_u16() as i16, &body.to_string())
+        .await
+    {
+        eprintln!(
+            "DB error while saving the response to order {}: {:?}",
+            order_id, err
+        );
+    }
+}
+
+/// another order of the user already has the client order id
+fn is_duplicate(err: &MarketError) -> bool {
+    matches!(err, MarketError::Db(sqlx::Error::Database(err)) if err.is_unique_violation())
+}
+
+/// the mode the user picked for his account, orders without their own mode use it
+async fn account_self_trade_prevention(db: &DB, user_id: &String) -> SelfTradePrevention {
+    match db.user.get_by_id(user_id).await {
+        Ok(user) => user.self_trade_prevention.parse().unwrap_or_default(),
+        Err(err) => {
+            eprintln!("DB error while reading self trade prevention: {:?}", err);
+            SelfTradePrevention::default()
+        }
     }
 }
 
@@ -44,274 +356,536 @@ async fn handle_order(
     State(state): State<AppState>,
     Path(opinion_id): Path<String>,
     Extension(user): Extension<UserModel>,
-    Json(order): Json<CreateOrderDto>,
+    headers: HeaderMap,
+    Json(mut order): Json<CreateOrderDto>,
 ) -> impl IntoResponse {
-    // check if user has enough money to add this order
-    if let Err(e) = order.validate() {
+    if let Err(message) = take_idempotency_key(&headers, &mut order) {
+        return (StatusCode::BAD_REQUEST, Json(json!({ "message": message }))).into_response();
+    }
+    let db = &state.db;
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    // a request sent again gets the answer of the first one, the order isn't placed twice
+    let client_order_id = order.client_order_id.clone();
+    if let Some(client_order_id) = &client_order_id {
+        match db
+            .order
+            .find_by_client_order_id(&user_id, client_order_id)
+            .await
+        {
+            Ok(Some(existing)) => return replay(existing),
+            Ok(None) => {}
+            Err(err) => {
+                eprintln!("DB error while looking up a client order id: {:?}", err);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({"message":"Error while placing order"})),
+                )
+                    .into_response();
+            }
+        }
+    }
+
+    let market = match state.market(&opinion_id).await {
+        Some(market) => market,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({"message":"Market not found"})),
+            )
+                .into_response();
+        }
+    };
+    if let Err(error) = check_order(market.params(), &mut order) {
+        return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+    }
+    if order.self_trade_prevention.is_none() {
+        order.self_trade_prevention = Some(account_self_trade_prevention(db, &user_id).await);
+    }
+    let order_id = Uuid::new_v4().to_string();
+    let taker = order.to_order(order_id.clone(), user_id.clone());
+    let placed = match order.trigger {
+        Some(trigger) => market.add_conditional(taker, order, trigger).await,
+        None => market.place(taker, order).await,
+    };
+    let err = match placed {
+        Ok(placement) => {
+            let mut body = json!({"message":"ok","order_id":order_id,"client_order_id":client_order_id,"status":placement.status});
+            if let Some(price) = placement.repriced {
+                body["price"] = json!(price);
+            }
+            if client_order_id.is_some() {
+                remember_response(db, &order_id, StatusCode::OK, &body).await;
+            }
+            return Json(body).into_response();
+        }
+        Err(err) => err,
+    };
+
+    // a request with the same client order id got in between the lookup above and the market
+    if is_duplicate(&err)
+        && let Some(client_order_id) = &client_order_id
+        && let Ok(Some(existing)) = db
+            .order
+            .find_by_client_order_id(&user_id, client_order_id)
+            .await
+    {
+        return replay(existing);
+    }
+    let (status, message) = placement_error(&err);
+    // refused before anything was recorded, there is no order to answer a retry from
+    if matches!(
+        err,
+        MarketError::InsufficientBalance
+            | MarketError::InsufficientShares
+            | MarketError::LimitExceeded(_)
+    ) {
+        return (status, Json(json!({ "message": message }))).into_response();
+    }
+
+    // nothing of the order got booked, either the market got resolved before the order reached it
+    // or its settlement was rolled back together with the hold
+    let body = json!({ "message": message, "order_id": order_id });
+    if client_order_id.is_some() {
+        remember_response(db, &order_id, status, &body).await;
+    }
+    (status, Json(body)).into_response()
+}
+
+/**
+ * places a list of orders in one round trip, a ladder of quotes for example
+ * every order is checked here, the market takes the holds of all of them in one transaction up front,
+ * so a batch the user can't pay for is rejected as a whole, the orders then go through matching one after another
+ */
+async fn handle_batch(
+    State(state): State<AppState>,
+    Path(opinion_id): Path<String>,
+    Extension(user): Extension<UserModel>,
+    Json(mut orders): Json<Vec<CreateOrderDto>>,
+) -> impl IntoResponse {
+    if orders.is_empty() || orders.len() > MAX_BATCH_SIZE {
         return (
             StatusCode::BAD_REQUEST,
-            Json(json!({ "message": "Trade failed","errors":e })),
+            Json(
+                json!({"message":format!("A batch holds between 1 and {} orders", MAX_BATCH_SIZE)}),
+            ),
         )
             .into_response();
     }
+    let market = match state.market(&opinion_id).await {
+        Some(market) => market,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({"message":"Market not found"})),
+            )
+                .into_response();
+        }
+    };
+    let mut client_order_ids = HashSet::new();
+    for (index, order) in orders.iter_mut().enumerate() {
+        if let Err(error) = check_order(market.params(), order) {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"message":"Batch rejected","index":index,"error":error})),
+            )
+                .into_response();
+        }
+        if let Some(client_order_id) = &order.client_order_id
+            && !client_order_ids.insert(client_order_id.clone())
+        {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"message":"Batch rejected","index":index,"error":{"message":"client_order_id is used twice in this batch"}})),
+            )
+                .into_response();
+        }
+    }
+
     let db = &state.db;
     let user_id = user.id.expect("User Id must be part of jwt token");
-    if !hold_balance(&db, &user_id, &order).await {
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({"message":"You cannot trade with amount more than your balance"})),
-        )
-            .into_response();
+    // a batch is all or nothing, a retry of one that went through is rejected instead of replayed
+    let client_order_ids: Vec<String> = client_order_ids.into_iter().collect();
+    match db
+        .order
+        .find_used_client_order_ids(&user_id, &client_order_ids)
+        .await
+    {
+        Ok(used) if used.is_empty() => {}
+        Ok(used) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(json!({"message":"Batch rejected, client_order_id already used","client_order_ids":used})),
+            )
+                .into_response();
+        }
+        Err(err) => {
+            eprintln!("DB error while looking up client order ids: {:?}", err);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message":"Error while placing orders"})),
+            )
+                .into_response();
+        }
+    }
+    let stp = account_self_trade_prevention(db, &user_id).await;
+    let orders: Vec<(Order, CreateOrderDto)> = orders
+        .into_iter()
+        .map(|mut order| {
+            order.self_trade_prevention.get_or_insert(stp);
+            (
+                order.to_order(Uuid::new_v4().to_string(), user_id.clone()),
+                order,
+            )
+        })
+        .collect();
+    let client_order_ids: Vec<(String, Option<String>)> = orders
+        .iter()
+        .map(|(taker, order)| (taker.id.clone(), order.client_order_id.clone()))
+        .collect();
+    let placements = match market.place_batch(orders).await {
+        Ok(placements) => placements,
+        Err(err) if is_duplicate(&err) => {
+            return (
+                StatusCode::CONFLICT,
+                Json(json!({"message":"Batch rejected, client_order_id already used"})),
+            )
+                .into_response();
+        }
+        Err(err @ MarketError::Rejected { index, .. }) => {
+            let (status, message) = placement_error(&err);
+            return (
+                status,
+                Json(json!({"message":"Batch rejected","index":index,"error":{"message":message}})),
+            )
+                .into_response();
+        }
+        Err(err) => {
+            let (status, message) = placement_error(&err);
+            return (status, Json(json!({ "message": message }))).into_response();
+        }
+    };
+
+    let mut results = Vec::with_capacity(placements.len());
+    for ((order_id, client_order_id), placed) in client_order_ids.into_iter().zip(placements) {
+        let result = match placed {
+            Ok(placement) => {
+                let mut result = json!({
+                    "order_id": order_id,
+                    "client_order_id": client_order_id,
+                    "status": placement.status,
+                    "fills": placement.fills,
+                    "resting": placement.resting,
+                });
+                if let Some(price) = placement.repriced {
+                    result["price"] = json!(price);
+                }
+                result
+            }
+            Err(err) => {
+                let (_, message) = placement_error(&err);
+                json!({
+                    "order_id": order_id,
+                    "client_order_id": client_order_id,
+                    "status": OrderStatus::Cancelled,
+                    "error": message,
+                })
+            }
+        };
+        // a single order sent again with the client order id gets its part of the batch result
+        if client_order_id.is_some() {
+            remember_response(db, &order_id, StatusCode::OK, &result).await;
+        }
+        results.push(result);
     }
+    Json(json!({"message":"ok","orders":results})).into_response()
+}
 
-    let remaining = match_orders(&user_id, &opinion_id, &state, &order).await;
+/// removes a resting order from the book, only the user who placed the order can cancel it
+async fn cancel_order(
+    State(state): State<AppState>,
+    Path((opinion_id, order_id)): Path<(String, String)>,
+    Extension(user): Extension<UserModel>,
+) -> impl IntoResponse {
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    cancel_in_market(&state, &opinion_id, order_id, user_id).await
+}
 
-    if create_trades_and_update_order_book(&user_id, &opinion_id, &state, remaining, &order)
+/// the same as cancelling by order id, with the id the user gave the order
+async fn cancel_order_by_client_id(
+    State(state): State<AppState>,
+    Path(client_order_id): Path<String>,
+    Extension(user): Extension<UserModel>,
+) -> impl IntoResponse {
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    match state
+        .db
+        .order
+        .find_by_client_order_id(&user_id, &client_order_id)
         .await
-        .is_err()
     {
-        let mut tx = db.pool.begin().await.unwrap();
-        db.user
-            .release_balance(&mut *tx, &user_id, order.price * order.quantity)
-            .await
-            .unwrap();
-        tx.commit().await.unwrap();
+        Ok(Some(order)) => cancel_in_market(&state, &order.opinion_id, order.id, user_id).await,
+        Ok(None) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"message":"Order not found"})),
+        )
+            .into_response(),
+        Err(err) => {
+            eprintln!("DB error while looking up a client order id: {:?}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message":"Error while cancelling order"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn cancel_in_market(
+    state: &AppState,
+    opinion_id: &str,
+    order_id: String,
+    user_id: String,
+) -> Response {
+    let market = match state.market(opinion_id).await {
+        Some(market) => market,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({"message":"Order Book not found"})),
+            )
+                .into_response();
+        }
     };
-    Json("ok").into_response()
+
+    match market.cancel(order_id, user_id).await {
+        Ok(order) => Json(json!({"message":"Order cancelled","order":order})).into_response(),
+        Err(MarketError::OrderNotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"message":"Order not found"})),
+        )
+            .into_response(),
+        Err(MarketError::NotOwner) => (
+            StatusCode::FORBIDDEN,
+            Json(json!({"message":"You can only cancel your own orders"})),
+        )
+            .into_response(),
+        Err(err) => {
+            eprintln!("DB error while cancelling order: {:?}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message":"Error while cancelling order"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CancelAllQuery {
+    /// only orders in this market, every market if it is left out
+    opinion_id: Option<String>,
+}
+
+/// kill switch, cancels every resting and pending order of the user in one market or in all of them, see `cancel_in_markets`
+async fn cancel_all_orders(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserModel>,
+    Query(query): Query<CancelAllQuery>,
+) -> impl IntoResponse {
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    cancel_in_markets(
+        &state,
+        query.opinion_id.as_deref(),
+        Some(user_id),
+        CancelReason::User,
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+pub struct AdminCancelQuery {
+    opinion_id: Option<String>,
+    user_id: Option<String>,
+}
+
+/// the kill switch for admins, cancels every order of a user, of a market, or of a user in one market, see `cancel_in_markets`
+async fn admin_cancel_orders(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserModel>,
+    Query(query): Query<AdminCancelQuery>,
+) -> impl IntoResponse {
+    let admin_id = user.id.expect("User Id must be part of jwt token");
+    match state.db.user.is_admin(&admin_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(json!({"message":"Only admins can cancel orders of other users"})),
+            )
+                .into_response();
+        }
+        Err(err) => {
+            eprintln!("DB error while checking admin rights: {:?}", err);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message":"Error while cancelling orders"})),
+            )
+                .into_response();
+        }
+    }
+    if query.opinion_id.is_none() && query.user_id.is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"message":"Either opinion_id or user_id is required"})),
+        )
+            .into_response();
+    }
+    cancel_in_markets(
+        &state,
+        query.opinion_id.as_deref(),
+        query.user_id,
+        CancelReason::Admin,
+    )
+    .await
 }
 
 /**
- * find matching order
- * get unfulfilled quantity and list of trades that are match
+ * cancels the orders of `user_id`, or of everyone, in the market `opinion_id` or in every market
+ * this is not atomic across markets: each market cancels its orders in its own transaction,
+ * so the answer has a result for every market it went to, `cancelled` with the orders, `closed` for one
+ * resolved in the meantime or `failed` for one that kept its orders, those are listed in `failed` as well
  */
-async fn match_orders(
-    user_id: &String,
-    opinion_id: &String,
+async fn cancel_in_markets(
     state: &AppState,
-    order: &CreateOrderDto,
-) -> Option<(u16, Vec<TradeModel>)> {
-    let mut order_book = state.order_book.write().await;
-
-    return match order_book.get_mut(opinion_id) {
-        Some(book_orders) => match order.side {
-            Side::Against => {
-                // we will have to find a matching order price against current price to create a trade
-                // if someone willing to buy NO at 80 cents then someone has to buy YES at least at 20 cents or more
-                let mut trades: Vec<TradeModel> = vec![];
-                let match_price = 1000 - order.price;
-                let mut quantity = order.quantity;
-                let mut remove = 0;
-                for book_order in book_orders.favour.iter_mut().rev() {
-                    //this will be sorted ascending order and we will check from highest (last) so user can get at best price
-                    // and if the highest price is less than the match price then there is no possibility of a match
-                    // example someone is willing to buy NO at 80 then the match price will be 20
-                    // then if 20 is greater then highest available price in book order then there is no possibility of math
-                    // order will have to wait in order book as cant fulfilled immediately
-                    if match_price > book_order.price {
-                        break;
-                    }
-                    if user_id == &book_order.user_id {
-                        continue;
-                    }
-                    if book_order.quantity > quantity {
-                        let trade = TradeModel {
-                            id: None,
-                            quantity,
-                            opinion_id: opinion_id.clone(),
-                            favour_user_id: book_order.user_id.clone(),
-                            favour_price: book_order.price,
-                            against_price: 1000 - book_order.price,
-                            against_user_id: user_id.clone(),
-                        };
-                        trades.push(trade);
-                        book_order.quantity -= quantity;
-                        quantity = 0;
-                        break;
-                    } else {
-                        let trade = TradeModel {
-                            id: None,
-                            quantity: book_order.quantity,
-                            opinion_id: opinion_id.clone(),
-                            favour_user_id: book_order.user_id.clone(),
-                            favour_price: book_order.price,
-                            against_price: 1000 - book_order.price,
-                            against_user_id: user_id.clone(),
-                        };
-                        trades.push(trade);
-                        quantity -= book_order.quantity;
-                        remove += 1
-                    }
-                }
+    opinion_id: Option<&str>,
+    user_id: Option<String>,
+    reason: CancelReason,
+) -> Response {
+    let markets: Vec<(String, MarketHandle)> = match opinion_id {
+        Some(opinion_id) => match state.market(opinion_id).await {
+            Some(market) => vec![(opinion_id.to_string(), market)],
+            None => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({"message":"Order Book not found"})),
+                )
+                    .into_response();
+            }
+        },
+        None => state
+            .markets
+            .read()
+            .await
+            .iter()
+            .map(|(opinion_id, market)| (opinion_id.clone(), market.clone()))
+            .collect(),
+    };
 
-                for _ in 0..remove {
-                    book_orders.favour.pop();
-                }
-                Some((quantity, trades))
+    let mut results = HashMap::new();
+    let mut failed = Vec::new();
+    for (opinion_id, market) in markets {
+        let result = match market.cancel_all(user_id.clone(), reason).await {
+            Ok(orders) => json!({"status":"cancelled","orders":orders}),
+            // resolved in the meantime, its orders are cancelled already
+            Err(MarketError::Closed) => json!({"status":"closed"}),
+            Err(err) => {
+                eprintln!(
+                    "Error while cancelling orders in market {}: {:?}",
+                    opinion_id, err
+                );
+                failed.push(opinion_id.clone());
+                json!({"status":"failed"})
             }
-            Side::Favour => {
-                // we will have to find matching NO order
-                // if user is willing to buy YES at 60 then check in orderbook if there is any order for NO at least at price 40
-                // if there is order for 45 then we will give user the best price which is 55 for YES
-                // to check above condition the array needs to be sorted ascending
-                // we will reverse traverse reverse to give user the best price
-
-                let mut trades: Vec<TradeModel> = vec![];
-                let match_price = 1000 - order.price;
-                let mut quantity = order.quantity;
-                let mut remove = 0;
-
-                for book_order in book_orders.against.iter_mut().rev() {
-                    // we will break if the highest price available for NO is less than minimum match price
-                    if match_price > book_order.price {
-                        break;
-                    }
-                    if user_id == &book_order.user_id {
-                        continue;
-                    }
-                    if book_order.quantity > quantity {
-                        let trade = TradeModel::new(
-                            None,
-                            opinion_id.clone(),
-                            user_id.clone(),
-                            book_order.user_id.clone(),
-                            1000 - book_order.price,
-                            book_order.price,
-                            quantity,
-                        );
-                        trades.push(trade);
-                        book_order.quantity -= quantity;
-                        quantity = 0;
-                        break;
-                    } else {
-                        // price given by user is just a price to check price against book orders
-                        // or we can say its maximum that one user can pay
-                        // actual trade will happen on the book price to be able to give best price to the user
-                        let trade = TradeModel::new(
-                            None,
-                            opinion_id.clone(),
-                            user_id.clone(),
-                            book_order.user_id.clone(),
-                            1000 - book_order.price,
-                            book_order.price,
-                            book_order.quantity,
-                        );
-                        trades.push(trade);
-                        quantity -= book_order.quantity;
-                        remove += 1
-                    }
-                }
+        };
+        results.insert(opinion_id, result);
+    }
 
-                for _ in 0..remove {
-                    book_orders.against.pop();
-                }
+    if !failed.is_empty() {
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"message":"Some markets could not cancel their orders, the others did","markets":results,"failed":failed})),
+        )
+            .into_response();
+    }
+    Json(json!({"message":"Orders cancelled","markets":results})).into_response()
+}
 
-                Some((quantity, trades))
-            }
-        },
-        None => None,
+/// changes price and/or quantity of a resting order, see `Market::amend` for how priority is kept
+async fn amend_order(
+    State(state): State<AppState>,
+    Path((opinion_id, order_id)): Path<(String, String)>,
+    Extension(user): Extension<UserModel>,
+    Json(amend): Json<AmendOrderDto>,
+) -> impl IntoResponse {
+    if let Err(e) = amend.validate() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "message": "Amend failed","errors":e })),
+        )
+            .into_response();
+    }
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    let market = match state.market(&opinion_id).await {
+        Some(market) => market,
+        None => {
+            return (
+                StatusCode::NOT_FOUND,
+                Json(json!({"message":"Order Book not found"})),
+            )
+                .into_response();
+        }
     };
-}
+    let params = market.params();
+    if let Some(price) = amend.price
+        && !params.accepts(price)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "message": params.price_error() })),
+        )
+            .into_response();
+    }
 
-/**
- * if there are unfulfilled quantities and fulfilled trades or any of them
- * create trade in db and add into order book
- * */
-async fn create_trades_and_update_order_book(
-    user_id: &String,
-    opinion_id: &String,
-    state: &AppState,
-    remaining: Option<(u16, Vec<TradeModel>)>,
-    order: &CreateOrderDto,
-) -> Result<bool, String> {
-    let db = &state.db;
-    let mut order_book = state.order_book.write().await;
-
-    if let Some((quantity, trades)) = remaining {
-        match order.side {
-            Side::Against => {
-                if quantity > 0 {
-                    if let Some(order_book) = order_book.get_mut(opinion_id) {
-                        order_book.against.push(Order {
-                            user_id: user_id.clone(),
-                            quantity,
-                            price: order.price,
-                            side: order.side.clone(),
-                        });
-                        order_book.against.sort_by_key(|o| o.price);
-                    }
-                };
-                for trade in trades.iter() {
-                    let mut tx = db.pool.begin().await.unwrap();
-                    db.trade.create(&mut *tx, &trade).await.unwrap();
-                    // cleaning up hold balance if trade happens at lower price then the user requested.
-                    db.user
-                        .release_balance(
-                            &mut *tx,
-                            &user_id,
-                            trade.quantity * (order.price - trade.against_price),
-                        )
-                        .await
-                        .unwrap();
-                    tx.commit().await.unwrap();
-                }
-            }
-            Side::Favour => {
-                // if some quantity is remaining to fill push and sort
-                if quantity > 0 {
-                    if let Some(order_book) = order_book.get_mut(opinion_id) {
-                        order_book.favour.push(Order {
-                            user_id: user_id.clone(),
-                            quantity,
-                            price: order.price,
-                            side: order.side.clone(),
-                        });
-                        order_book.favour.sort_by_key(|o| o.price);
-                    }
-                };
-                for trade in trades.iter() {
-                    let mut tx = db.pool.begin().await.unwrap();
-                    db.trade.create(&mut *tx, &trade).await.unwrap();
-                    db.user
-                        .release_balance(
-                            &mut *tx,
-                            &user_id,
-                            trade.quantity * (order.price - trade.favour_price),
-                        )
-                        .await
-                        .unwrap();
-                    tx.commit().await.unwrap();
-                }
+    match market
+        .amend(order_id.clone(), user_id, amend.price, amend.quantity)
+        .await
+    {
+        Ok(Amended::Unchanged(order)) => {
+            Json(json!({"message":"Nothing to amend","order":order})).into_response()
+        }
+        Ok(Amended::InPlace(order)) => {
+            Json(json!({"message":"Order amended","order":order})).into_response()
+        }
+        Ok(Amended::Requeued(placement)) => {
+            let mut body =
+                json!({"message":"Order amended","order_id":order_id,"status":placement.status});
+            if let Some(price) = placement.repriced {
+                body["price"] = json!(price);
             }
+            Json(body).into_response()
         }
-    } else {
-        // if there are 0 orders for a trade
-        let order = Order {
-            user_id: user_id.clone(),
-            quantity: order.quantity,
-            price: order.price,
-            side: order.side.clone(),
-        };
-        match &order.side {
-            Side::Against => {
-                order_book.insert(
-                    opinion_id.clone(),
-                    OrderBook {
-                        favour: vec![],
-                        against: vec![order],
-                    },
-                );
+        Err(MarketError::OrderNotFound) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"message":"Order not found"})),
+        )
+            .into_response(),
+        Err(MarketError::NotOwner) => (
+            StatusCode::FORBIDDEN,
+            Json(json!({"message":"You can only amend your own orders"})),
+        )
+            .into_response(),
+        Err(err) => match order_error(&err) {
+            Some((status, message)) => {
+                (status, Json(json!({ "message": message }))).into_response()
             }
-            Side::Favour => {
-                order_book.insert(
-                    opinion_id.clone(),
-                    OrderBook {
-                        favour: vec![order],
-                        against: vec![],
-                    },
-                );
+            None => {
+                eprintln!("Error while amending order: {:?}", err);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({"message":"Error while amending order"})),
+                )
+                    .into_response()
             }
-        };
+        },
     }
-
-    return Ok(true);
 }
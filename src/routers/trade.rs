@@ -7,17 +7,18 @@ use axum::{
 };
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tokio::sync::RwLockReadGuard;
 
 use crate::{
-    db::user::UserModel,
+    db::{db::DB, user::UserModel},
     middlewares::auth::auth_middleware,
-    state::{AppState, OrderBook, Side},
+    money::{Price, Quantity},
+    state::{Action, AppState, Side},
 };
 
 pub fn trade_router() -> Router<AppState> {
     Router::new()
         .route("/trades", get(get_all_trades))
+        .route("/positions", get(get_positions))
         .layer(middleware::from_fn(auth_middleware))
 }
 
@@ -40,8 +41,10 @@ pub async fn get_all_trades(
     match trades {
         Ok(trades) => match query.active {
             Some(true) => {
-                let order_book = state.order_book.read().await;
-                let orders = get_orders_by_user(&order_book, &user.id.unwrap());
+                let orders = match get_orders_by_user(&state.db, &user.id.unwrap()).await {
+                    Ok(orders) => orders,
+                    Err(_) => return Json("Some Error occurred").into_response(),
+                };
                 Json(json!({
                     "unfulfilled": orders,
                     "fulfilled": trades
@@ -58,44 +61,48 @@ pub async fn get_all_trades(
     }
 }
 
-use std::collections::HashMap;
+/// shares the user holds in unresolved markets, `reservedQuantity` of them are offered by resting sells
+pub async fn get_positions(
+    State(state): State<AppState>,
+    Extension(user): Extension<UserModel>,
+) -> impl IntoResponse {
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    match state.db.position.find_open_by_user_id(&user_id).await {
+        Ok(positions) => Json(json!({ "positions": positions })).into_response(),
+        Err(_) => Json("Some Error occurred").into_response(),
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OrderWithOpinion {
+    pub id: String,
     pub opinion_id: String,
     pub user_id: String,
-    pub quantity: u16,
-    pub price: u16,
+    pub quantity: Quantity,
+    pub price: Price,
     pub side: Side,
+    pub action: Action,
 }
 
-pub fn get_orders_by_user(
-    order_book: &RwLockReadGuard<'_, HashMap<String, OrderBook>>,
-    user_id: &str,
-) -> Vec<OrderWithOpinion> {
-    let mut user_orders = Vec::new();
-
-    for (opinion_id, book) in order_book.iter() {
-        for order in &book.favour {
-            if order.user_id == user_id {
-                user_orders.push((opinion_id.clone(), order.clone()));
-            }
-        }
-        for order in &book.against {
-            if order.user_id == user_id {
-                user_orders.push((opinion_id.clone(), order.clone()));
-            }
-        }
-    }
-
-    user_orders
+/// orders of the user still resting in a book, `quantity` is their open quantity
+pub async fn get_orders_by_user(
+    db: &DB,
+    user_id: &String,
+) -> Result<Vec<OrderWithOpinion>, sqlx::Error> {
+    let orders = db.order.find_open_by_user_id(user_id).await?;
+    Ok(orders
         .into_iter()
-        .map(|(opinion_id, order)| OrderWithOpinion {
-            opinion_id: opinion_id,
-            user_id: order.user_id,
-            quantity: order.quantity,
-            price: order.price,
-            side: order.side,
+        .filter_map(|order| {
+            Some(OrderWithOpinion {
+                side: order.side.parse().ok()?,
+                action: order.action.parse().unwrap_or_default(),
+                id: order.id,
+                opinion_id: order.opinion_id,
+                user_id: order.user_id,
+                quantity: order.remaining_quantity,
+                price: order.price,
+            })
         })
-        .collect()
+        .collect())
 }
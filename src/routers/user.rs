@@ -1,19 +1,22 @@
 use axum::{
     Extension, Json, Router,
     extract::{Path, State},
+    http::StatusCode,
     middleware::from_fn,
     response::IntoResponse,
-    routing::get,
+    routing::{get, put},
 };
+use serde::{Deserialize, Serialize};
 use serde_json::json;
 
 use crate::{
     db::{
         db::DB,
-        user::{UserModel, UserTransactionsModel},
+        user::{UserLimitsModel, UserModel, UserTransactionsModel},
     },
+    limits::Limits,
     middlewares::auth::auth_middleware,
-    state::AppState,
+    state::{AppState, SelfTradePrevention},
 };
 
 pub fn user_router() -> Router<AppState> {
@@ -23,7 +26,97 @@ pub fn user_router() -> Router<AppState> {
             "/transactions",
             get(get_user_transactions).route_layer(from_fn(auth_middleware)),
         )
+        .route(
+            "/self-trade-prevention",
+            put(update_self_trade_prevention).route_layer(from_fn(auth_middleware)),
+        )
         .route("/{user_id}", get(get_user_by_id))
+        .route(
+            "/{user_id}/limits",
+            put(update_user_limits).route_layer(from_fn(auth_middleware)),
+        )
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SelfTradePreventionDto {
+    mode: SelfTradePrevention,
+}
+
+/// sets the self trade prevention mode used by orders that don't pick their own
+pub async fn update_self_trade_prevention(
+    State(db): State<DB>,
+    Extension(user): Extension<UserModel>,
+    Json(dto): Json<SelfTradePreventionDto>,
+) -> impl IntoResponse {
+    let user_id = user.id.expect("User Id must be part of jwt token");
+    match db
+        .user
+        .update_self_trade_prevention(&user_id, dto.mode.as_str())
+        .await
+    {
+        Ok(_) => {
+            Json(json!({"message":"Self trade prevention updated","mode":dto.mode})).into_response()
+        }
+        Err(err) => {
+            eprintln!("DB error: {:?}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message":"Error while updating self trade prevention"})),
+            )
+                .into_response()
+        }
+    }
+}
+
+/// sets the risk limits of an account, only admins can, a limit left out is removed
+pub async fn update_user_limits(
+    State(db): State<DB>,
+    Extension(user): Extension<UserModel>,
+    Path(user_id): Path<String>,
+    Json(limits): Json<UserLimitsModel>,
+) -> impl IntoResponse {
+    let admin_id = user.id.expect("User Id must be part of jwt token");
+    match db.user.is_admin(&admin_id).await {
+        Ok(true) => {}
+        Ok(false) => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(json!({"message":"Only admins can set the limits of an account"})),
+            )
+                .into_response();
+        }
+        Err(err) => {
+            eprintln!("DB error while checking admin rights: {:?}", err);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message":"Error while updating limits"})),
+            )
+                .into_response();
+        }
+    }
+    if !Limits::from(limits.clone()).is_valid() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"message":"Limits must be positive"})),
+        )
+            .into_response();
+    }
+    match db.user.update_limits(&user_id, &limits).await {
+        Ok(true) => Json(json!({"message":"Limits updated","limits":limits})).into_response(),
+        Ok(false) => (
+            StatusCode::NOT_FOUND,
+            Json(json!({"message":"User not found"})),
+        )
+            .into_response(),
+        Err(err) => {
+            eprintln!("DB error: {:?}", err);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({"message":"Error while updating limits"})),
+            )
+                .into_response()
+        }
+    }
 }
 
 pub async fn get_user_transactions(
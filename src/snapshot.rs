@@ -0,0 +1,208 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{fs, io::AsyncWriteExt};
+
+use crate::{
+    market::MarketError,
+    state::{OrderBook, SharedMarkets},
+};
+
+/// how often the books of all markets are written to disk
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// older snapshots are deleted once a new one is written, the ones kept are fallbacks for a corrupt newest one
+const SNAPSHOTS_KEPT: usize = 3;
+
+/// bumped whenever the layout of `Snapshot` changes, snapshots of another version are skipped on startup
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// first word of every snapshot file
+const MAGIC: &str = "opinion-trading-snapshot";
+
+/**
+ * the books of all running markets, each with the sequence number of the last journal entry it contains
+ * every market is captured between two of its commands, different markets at slightly different times
+ */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub taken_at: DateTime<Utc>,
+    pub markets: HashMap<String, MarketSnapshot>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MarketSnapshot {
+    pub sequence: i64,
+    pub book: OrderBook,
+}
+
+/// where snapshots are kept, `SNAPSHOT_DIR` or `snapshots` in the working directory
+pub fn dir() -> PathBuf {
+    env::var("SNAPSHOT_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from("snapshots"))
+}
+
+fn checksum(body: &str) -> String {
+    format!("{:x}", Sha256::digest(body.as_bytes()))
+}
+
+/**
+ * a header line with the version and the sha256 of the body, then the snapshot as json
+ * a file that was only partly written or got changed afterwards doesn't match its checksum
+ */
+fn encode(snapshot: &Snapshot) -> String {
+    let body = serde_json::to_string(snapshot).expect("snapshots serialize to json");
+    format!(
+        "{} {} {}\n{}",
+        MAGIC,
+        SNAPSHOT_VERSION,
+        checksum(&body),
+        body
+    )
+}
+
+fn decode(contents: &str) -> Result<Snapshot, String> {
+    let (header, body) = contents
+        .split_once('\n')
+        .ok_or("no header line, the file is cut off")?;
+    let mut fields = header.split(' ');
+    let (Some(MAGIC), Some(version), Some(sum), None) =
+        (fields.next(), fields.next(), fields.next(), fields.next())
+    else {
+        return Err("not a snapshot".to_string());
+    };
+    if version.parse::<u32>() != Ok(SNAPSHOT_VERSION) {
+        return Err(format!(
+            "version {} where {} is expected",
+            version, SNAPSHOT_VERSION
+        ));
+    }
+    if checksum(body) != sum {
+        return Err(
+            "checksum doesn't match, the file is corrupt or only partly written".to_string(),
+        );
+    }
+    serde_json::from_str(body).map_err(|err| err.to_string())
+}
+
+fn file_name(taken_at: DateTime<Utc>) -> String {
+    // zero padded so the names sort like the times
+    format!("snapshot-{:020}.snap", taken_at.timestamp_millis())
+}
+
+/// snapshot files in `dir`, newest first
+async fn snapshot_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut entries = fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name.starts_with("snapshot-") && name.ends_with(".snap") {
+            files.push(entry.path());
+        }
+    }
+    files.sort();
+    files.reverse();
+    Ok(files)
+}
+
+/**
+ * the newest snapshot in `dir` that reads back whole, one that doesn't is reported and skipped
+ * `None` without any usable snapshot, the books are then replayed from the start of the journal
+ */
+pub async fn load_latest(dir: &Path) -> Option<Snapshot> {
+    let files = match snapshot_files(dir).await {
+        Ok(files) => files,
+        Err(err) => {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                eprintln!(
+                    "Error while listing snapshots in {}: {:?}",
+                    dir.display(),
+                    err
+                );
+            }
+            return None;
+        }
+    };
+    for path in files {
+        let decoded = match fs::read_to_string(&path).await {
+            Ok(contents) => decode(&contents),
+            Err(err) => Err(err.to_string()),
+        };
+        match decoded {
+            Ok(snapshot) => return Some(snapshot),
+            Err(err) => eprintln!("Skipping snapshot {}: {}", path.display(), err),
+        }
+    }
+    None
+}
+
+/**
+ * captures every running market and writes them to a new file in `dir`, then drops the oldest files
+ * the file is written under a temporary name and renamed once it is complete
+ */
+pub async fn write_snapshot(markets: &SharedMarkets, dir: &Path) -> std::io::Result<PathBuf> {
+    // handles are cloned so no market waits on the lock while the others are captured
+    let handles: Vec<_> = markets
+        .read()
+        .await
+        .iter()
+        .map(|(opinion_id, market)| (opinion_id.clone(), market.clone()))
+        .collect();
+
+    let mut snapshot = Snapshot {
+        taken_at: Utc::now(),
+        markets: HashMap::with_capacity(handles.len()),
+    };
+    for (opinion_id, market) in handles {
+        match market.snapshot().await {
+            Ok(captured) => {
+                snapshot.markets.insert(opinion_id, captured);
+            }
+            // resolved in the meantime, nothing left to recover
+            Err(MarketError::Closed) => {}
+            // left out, it is replayed from the start of its journal on recovery
+            Err(err) => eprintln!("Error while capturing market {}: {:?}", opinion_id, err),
+        }
+    }
+
+    fs::create_dir_all(dir).await?;
+    let path = dir.join(file_name(snapshot.taken_at));
+    let partial = path.with_extension("snap.tmp");
+    let mut file = fs::File::create(&partial).await?;
+    file.write_all(encode(&snapshot).as_bytes()).await?;
+    file.sync_all().await?;
+    fs::rename(&partial, &path).await?;
+
+    for old in snapshot_files(dir).await?.into_iter().skip(SNAPSHOTS_KEPT) {
+        fs::remove_file(old).await?;
+    }
+    Ok(path)
+}
+
+/// writes a snapshot every `SNAPSHOT_INTERVAL`, a failed one is reported and the next interval tries again
+pub fn spawn_snapshots(markets: SharedMarkets, dir: PathBuf) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SNAPSHOT_INTERVAL);
+        // the first tick is right away, the books were just recovered
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if let Err(err) = write_snapshot(&markets, &dir).await {
+                eprintln!(
+                    "Error while writing a snapshot to {}: {:?}",
+                    dir.display(),
+                    err
+                );
+            }
+        }
+    });
+}
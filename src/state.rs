@@ -1,64 +1,904 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    str::FromStr,
+    sync::Arc,
+};
 
 use tokio::sync::RwLock;
 
-use crate::db::db::DB;
+use crate::{
+    amm::TradingMode,
+    conditional::Trigger,
+    db::{db::DB, opinion::OpinionModel},
+    limits::Limits,
+    market::MarketHandle,
+    money::{Price, Quantity},
+};
 use axum::extract::FromRef;
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use validator::Validate;
 
-pub type SharedOrderBook = Arc<RwLock<HashMap<String, OrderBook>>>;
+/// running markets by opinion id, the lock only guards the map, every book is owned by its market task
+pub type SharedMarkets = Arc<RwLock<HashMap<String, MarketHandle>>>;
 #[derive(Clone, FromRef)]
 pub struct AppState {
     pub db: DB,
-    pub order_book: SharedOrderBook,
+    pub markets: SharedMarkets,
 }
 
 impl AppState {
     pub async fn new() -> Self {
         Self {
             db: DB::new().await,
-            order_book: Arc::new(RwLock::new(HashMap::new())),
+            markets: Arc::new(RwLock::new(HashMap::new())),
         }
     }
+
+    pub async fn market(&self, opinion_id: &str) -> Option<MarketHandle> {
+        self.markets.read().await.get(opinion_id).cloned()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredOrderBook")]
 pub struct OrderBook {
-    pub favour: Vec<Order>,
-    pub against: Vec<Order>,
+    pub favour: BookSide,
+    pub against: BookSide,
+    /// payout of the market, sells are keyed by the complement of their price
+    pub payout: Price,
+    #[serde(default)]
+    next_seq: u64,
+}
+
+/// a book as it is serialized, its sides are plain lists that get their price levels back from the payout
+#[derive(Deserialize)]
+struct StoredOrderBook {
+    favour: Vec<Order>,
+    against: Vec<Order>,
+    payout: Price,
+    #[serde(default)]
+    next_seq: u64,
+}
+
+impl From<StoredOrderBook> for OrderBook {
+    fn from(stored: StoredOrderBook) -> Self {
+        let mut book = OrderBook::empty(stored.payout);
+        // orders keep their sequence numbers, so every level gets back its queue order
+        for order in stored.favour.into_iter().chain(stored.against) {
+            let side = order.book_side();
+            let price = order.book_price(book.payout);
+            book.side_mut(&side).insert(order, price);
+        }
+        book.next_seq = stored.next_seq;
+        book
+    }
 }
 
 impl OrderBook {
-    pub fn empty() -> Self {
+    pub fn empty(payout: Price) -> Self {
+        Self {
+            favour: BookSide::default(),
+            against: BookSide::default(),
+            payout,
+            next_seq: 0,
+        }
+    }
+
+    pub fn side(&self, side: &Side) -> &BookSide {
+        match side {
+            Side::Favour => &self.favour,
+            Side::Against => &self.against,
+        }
+    }
+
+    pub fn side_mut(&mut self, side: &Side) -> &mut BookSide {
+        match side {
+            Side::Favour => &mut self.favour,
+            Side::Against => &mut self.against,
+        }
+    }
+
+    /// puts the order at the back of the queue of its price level, it gets a new sequence number
+    /// so anything inserted again (price change, size increase) loses its time priority
+    pub fn insert(&mut self, mut order: Order) {
+        self.next_seq += 1;
+        order.seq = self.next_seq;
+        let side = order.book_side();
+        let price = order.book_price(self.payout);
+        self.side_mut(&side).insert(order, price);
+    }
+
+    pub fn get(&self, order_id: &str) -> Option<&Order> {
+        self.favour
+            .get(order_id)
+            .or_else(|| self.against.get(order_id))
+    }
+
+    pub fn get_mut(&mut self, order_id: &str) -> Option<&mut Order> {
+        match self.favour.get_mut(order_id) {
+            Some(order) => Some(order),
+            None => self.against.get_mut(order_id),
+        }
+    }
+
+    /// resting orders on both sides whose expiry time has passed
+    pub fn expired(&self, now: DateTime<Utc>) -> Vec<Order> {
+        self.favour
+            .iter()
+            .chain(self.against.iter())
+            .filter(|o| o.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    /// removes a resting order from whichever side it sits on and returns it
+    pub fn remove(&mut self, order_id: &str) -> Option<Order> {
+        self.favour
+            .remove(order_id)
+            .or_else(|| self.against.remove(order_id))
+    }
+
+    /// what the routes get to read of the book at `now`, see `BookView`
+    pub fn view(&self, now: DateTime<Utc>) -> BookView {
+        BookView {
+            payout: self.payout,
+            favour: self.favour.view(now),
+            against: self.against.view(now),
+        }
+    }
+}
+
+/**
+ * the part of a book the routes read, the market publishes one after every change instead of a copy of the whole book
+ * it keeps the best `MAX_DEPTH_LEVELS` levels of each side that had a live order when it was taken,
+ * with the expiry of every order so one that runs out before the sweeper removes it is still left out
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct BookView {
+    payout: Price,
+    pub favour: SideView,
+    pub against: SideView,
+}
+
+/// visible quantity and expiry of one order in a `SideView`
+type ViewOrder = (Quantity, Option<DateTime<Utc>>);
+
+/// best levels of one side, best first, each with its orders in queue order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SideView {
+    levels: Vec<(Price, Vec<ViewOrder>)>,
+}
+
+impl SideView {
+    /// best price an incoming order could trade at now, orders that expired since the view was taken don't count
+    pub fn touch(&self, now: DateTime<Utc>) -> Option<Price> {
+        self.live(now).next().map(|(price, _)| price)
+    }
+
+    /**
+     * total quantity and number of orders of the best `levels` prices at `now`,
+     * expired orders are left out of both, a level with nothing else is skipped
+     */
+    pub fn depth(&self, levels: usize, now: DateTime<Utc>) -> Vec<DepthLevel> {
+        self.live(now)
+            .map(|(price, live)| DepthLevel {
+                price,
+                quantity: live
+                    .iter()
+                    .fold(Quantity::ZERO, |total, visible| total + *visible),
+                orders: live.len(),
+            })
+            .take(levels)
+            .collect()
+    }
+
+    /// levels with the visible quantity of the orders that haven't expired at `now`, empty ones skipped
+    fn live(&self, now: DateTime<Utc>) -> impl Iterator<Item = (Price, Vec<Quantity>)> + '_ {
+        self.levels.iter().filter_map(move |(price, orders)| {
+            let live: Vec<Quantity> = orders
+                .iter()
+                .filter(|(_, expires_at)| expires_at.is_none_or(|expires_at| expires_at > now))
+                .map(|(visible, _)| *visible)
+                .collect();
+            (!live.is_empty()).then_some((*price, live))
+        })
+    }
+}
+
+impl BookView {
+    /**
+     * the best `levels` price levels of both sides at `now`, without anything that tells who placed the orders
+     * the YES probability is the middle between the best YES bid and the cheapest YES on offer
+     * (what the best NO bid leaves), or whichever of them exists
+     */
+    pub fn depth(&self, levels: usize, now: DateTime<Utc>) -> MarketDepth {
+        let payout = self.payout.get() as f64;
+        let yes_bid = self.favour.touch(now);
+        let yes_ask = self
+            .against
+            .touch(now)
+            .map(|price| price.complement(self.payout));
+        let yes_price = match (yes_bid, yes_ask) {
+            (Some(bid), Some(ask)) => Some((bid.get() + ask.get()) as f64 / 2.0),
+            (Some(price), None) | (None, Some(price)) => Some(price.get() as f64),
+            (None, None) => None,
+        };
+        MarketDepth {
+            favour: self.favour.depth(levels, now),
+            against: self.against.depth(levels, now),
+            yes_probability: yes_price.map(|price| price / payout),
+            no_probability: yes_price.map(|price| (payout - price) / payout),
+        }
+    }
+}
+
+/// price levels per side a depth request gets unless it asks for a number, and the most it can ask for
+pub const DEFAULT_DEPTH_LEVELS: usize = 10;
+pub const MAX_DEPTH_LEVELS: usize = 100;
+
+/// `levels=N` of a depth request
+pub fn depth_levels(levels: Option<usize>) -> usize {
+    levels
+        .unwrap_or(DEFAULT_DEPTH_LEVELS)
+        .clamp(1, MAX_DEPTH_LEVELS)
+}
+
+/// aggregated view of a book, safe to show to anyone
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketDepth {
+    /// bids for YES, best first, sells of NO show up here at their YES price
+    pub favour: Vec<DepthLevel>,
+    /// bids for NO, best first
+    pub against: Vec<DepthLevel>,
+    /// `None` while both sides are empty
+    pub yes_probability: Option<f64>,
+    pub no_probability: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DepthLevel {
+    pub price: Price,
+    pub quantity: Quantity,
+    pub orders: usize,
+}
+
+/// one side of the book, price levels each holding a FIFO queue of resting orders
+/// best price for both sides is the highest one as both are bids for their own outcome
+/// levels are keyed by the book price, so sells of the other outcome queue next to the bids
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(into = "Vec<Order>")]
+pub struct BookSide {
+    levels: BTreeMap<Price, VecDeque<Order>>,
+    // order id -> price level, so lookups and cancels don't have to scan the whole side
+    index: HashMap<String, Price>,
+}
+
+impl BookSide {
+    fn insert(&mut self, order: Order, price: Price) {
+        self.index.insert(order.id.clone(), price);
+        self.levels.entry(price).or_default().push_back(order);
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    pub fn best_price(&self) -> Option<Price> {
+        self.levels.keys().next_back().copied()
+    }
+
+    /// best price an incoming order could trade at now, expired orders waiting for the sweeper don't count
+    pub fn touch(&self, now: DateTime<Utc>) -> Option<Price> {
+        self.levels
+            .iter()
+            .rev()
+            .find(|(_, queue)| queue.iter().any(|order| !order.is_expired(now)))
+            .map(|(price, _)| *price)
+    }
+
+    /// the best `MAX_DEPTH_LEVELS` levels with an order that hasn't expired at `now`, without the expired ones
+    fn view(&self, now: DateTime<Utc>) -> SideView {
+        SideView {
+            levels: self
+                .levels
+                .iter()
+                .rev()
+                .filter_map(|(price, queue)| {
+                    let live: Vec<ViewOrder> = queue
+                        .iter()
+                        .filter(|order| !order.is_expired(now))
+                        .map(|order| (order.visible(), order.expires_at))
+                        .collect();
+                    (!live.is_empty()).then_some((*price, live))
+                })
+                .take(MAX_DEPTH_LEVELS)
+                .collect(),
+        }
+    }
+
+    /// resting orders in priority order, best price first and oldest first within a price
+    pub fn iter(&self) -> impl Iterator<Item = &Order> {
+        self.levels.values().rev().flat_map(|queue| queue.iter())
+    }
+
+    pub fn get(&self, order_id: &str) -> Option<&Order> {
+        let price = self.index.get(order_id)?;
+        self.levels.get(price)?.iter().find(|o| o.id == order_id)
+    }
+
+    pub fn get_mut(&mut self, order_id: &str) -> Option<&mut Order> {
+        let price = self.index.get(order_id)?;
+        self.levels
+            .get_mut(price)?
+            .iter_mut()
+            .find(|o| o.id == order_id)
+    }
+
+    pub fn remove(&mut self, order_id: &str) -> Option<Order> {
+        let price = self.index.remove(order_id)?;
+        let queue = self.levels.get_mut(&price)?;
+        let position = queue.iter().position(|o| o.id == order_id)?;
+        let order = queue.remove(position);
+        if queue.is_empty() {
+            self.levels.remove(&price);
+        }
+        order
+    }
+
+    /**
+     * works out how `quantity` would fill against this side without touching it, walking from the
+     * best price down to `limit` and from the oldest order to the newest inside each level
+     * hitting an own order applies `stp` instead of trading, so the book never stays crossed
+     * expired orders are skipped, they are waiting for the expiry sweeper to release them
+     * an iceberg trades its visible slice and can be reached again with the next slice after the rest of the level
+     */
+    pub fn find_matches(
+        &self,
+        limit: Price,
+        quantity: Quantity,
+        user_id: &str,
+        now: DateTime<Utc>,
+        stp: SelfTradePrevention,
+    ) -> Matched {
+        let mut matched = Matched::unmatched(quantity);
+
+        for queue in self.levels.range(limit..).rev().map(|(_, queue)| queue) {
+            // an iceberg that runs out of its slice queues again behind the level with a new one
+            let mut refreshed = VecDeque::new();
+            let mut orders = queue.iter();
+            while let Some(mut order) = orders.next().cloned().or_else(|| refreshed.pop_front()) {
+                if matched.remaining.is_zero() {
+                    break;
+                }
+                if order.is_expired(now) {
+                    continue;
+                }
+
+                if order.user_id == user_id {
+                    let (resting_cancelled, incoming_cancelled) = match stp {
+                        SelfTradePrevention::CancelNewest => (Quantity::ZERO, matched.remaining),
+                        SelfTradePrevention::CancelOldest => (order.quantity, Quantity::ZERO),
+                        SelfTradePrevention::CancelBoth => (order.quantity, matched.remaining),
+                        SelfTradePrevention::DecrementAndCancel => {
+                            let decrement = order.quantity.min(matched.remaining);
+                            (decrement, decrement)
+                        }
+                    };
+                    if !resting_cancelled.is_zero() {
+                        matched
+                            .self_trade_cancels
+                            .push((order.clone(), resting_cancelled));
+                    }
+                    matched.cancelled += incoming_cancelled;
+                    matched.remaining -= incoming_cancelled;
+                } else {
+                    let filled = matched.remaining.min(order.visible());
+                    matched.fills.push((order.clone(), filled));
+                    matched.remaining -= filled;
+                    if filled < order.quantity && order.reduce(filled) {
+                        refreshed.push_back(order);
+                    }
+                }
+            }
+            if matched.remaining.is_zero() {
+                break;
+            }
+        }
+        matched
+    }
+}
+
+/// what happened to an incoming order that walked one side of the book
+#[derive(Debug, Default)]
+pub struct Matched {
+    /// resting orders (as they were before the fill) with the quantity filled against them
+    pub fills: Vec<(Order, Quantity)>,
+    /// own resting orders hit by self trade prevention with the quantity cancelled off them
+    pub self_trade_cancels: Vec<(Order, Quantity)>,
+    /// quantity of the incoming order cancelled by self trade prevention
+    pub cancelled: Quantity,
+    /// quantity of the incoming order that is neither filled nor cancelled
+    pub remaining: Quantity,
+}
+
+impl Matched {
+    pub fn unmatched(quantity: Quantity) -> Self {
         Self {
-            favour: vec![],
-            against: vec![],
+            remaining: quantity,
+            ..Default::default()
         }
     }
 }
 
+impl From<BookSide> for Vec<Order> {
+    fn from(side: BookSide) -> Self {
+        // oldest first inside a level so a round trip keeps the queue order
+        side.levels.into_values().rev().flatten().collect()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Order {
+    pub id: String,
     pub user_id: String,
-    pub quantity: u16,
-    pub price: u16,
+    pub quantity: Quantity,
+    pub price: Price,
     pub side: Side,
+    #[serde(default)]
+    pub action: Action,
+    #[serde(default)]
+    pub seq: u64,
+    pub created_at: DateTime<Utc>,
+    // good till date, the order is taken out of the book once this passes
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub iceberg: Option<Iceberg>,
+    /// the mode it was placed with, an amend that sends it through matching again uses it as well
+    #[serde(default)]
+    pub self_trade_prevention: SelfTradePrevention,
+    #[serde(default)]
+    pub client_order_id: Option<String>,
+    /// kept with the time in force for an amend that sends the order through matching again
+    #[serde(default)]
+    pub post_only: Option<PostOnly>,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+}
+
+/// an order that shows `display` of its quantity at a time, the rest is a hidden reserve
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Iceberg {
+    /// size of every slice
+    pub display: Quantity,
+    /// what is left of the slice on display
+    pub visible: Quantity,
+}
+
+impl Order {
+    pub fn new(id: String, user_id: String, quantity: Quantity, price: Price, side: Side) -> Self {
+        Self {
+            id,
+            user_id,
+            quantity,
+            price,
+            side,
+            action: Action::Buy,
+            seq: 0,
+            created_at: Utc::now(),
+            expires_at: None,
+            iceberg: None,
+            self_trade_prevention: SelfTradePrevention::default(),
+            client_order_id: None,
+            post_only: None,
+            time_in_force: TimeInForce::default(),
+        }
+    }
+
+    /// the part of the quantity that shows in the depth, all of it unless the order is an iceberg
+    pub fn visible(&self) -> Quantity {
+        match self.iceberg {
+            Some(iceberg) => iceberg.visible.min(self.quantity),
+            None => self.quantity,
+        }
+    }
+
+    /**
+     * takes `quantity` off an order that keeps some of it, an iceberg takes it off its slice first
+     * returns true if that used up the slice, the iceberg then shows a new one from its reserve
+     * and has to go to the back of the queue of its price level
+     */
+    pub fn reduce(&mut self, quantity: Quantity) -> bool {
+        self.quantity -= quantity;
+        let Some(iceberg) = self.iceberg.as_mut() else {
+            return false;
+        };
+        iceberg.visible -= quantity.min(iceberg.visible);
+        if !iceberg.visible.is_zero() {
+            return false;
+        }
+        iceberg.visible = iceberg.display.min(self.quantity);
+        true
+    }
+
+    /// lowers the order to `quantity` in place, an iceberg takes it off its hidden reserve first
+    pub fn amend(&mut self, quantity: Quantity) {
+        self.quantity = quantity;
+        if let Some(iceberg) = self.iceberg.as_mut() {
+            iceberg.visible = iceberg.visible.min(quantity);
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// side of the book the order rests on, selling one outcome is the same as bidding for the other
+    pub fn book_side(&self) -> Side {
+        match self.action {
+            Action::Buy => self.side.clone(),
+            Action::Sell => self.side.opposite(),
+        }
+    }
+
+    /// price on its book side, selling YES at 700 is the same as buying NO at 300
+    pub fn book_price(&self, payout: Price) -> Price {
+        match self.action {
+            Action::Buy => self.price,
+            Action::Sell => self.price.complement(payout),
+        }
+    }
+}
+
+/**
+ * pricing and risk limits of one market, stored with its opinion
+ * a winning share pays `payout`, orders are priced inside `min_price..=max_price` in steps of `tick_size`
+ * a market in `amm` mode trades against a market maker with `liquidity` instead of the book
+ */
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct MarketParams {
+    pub payout: Price,
+    pub tick_size: Price,
+    pub min_price: Price,
+    pub max_price: Price,
+    #[serde(flatten)]
+    pub limits: Limits,
+    pub trading_mode: TradingMode,
+    pub liquidity: Option<Quantity>,
+}
+
+impl Default for MarketParams {
+    fn default() -> Self {
+        Self {
+            payout: Price::new(1000),
+            tick_size: Price::new(1),
+            min_price: Price::new(100),
+            max_price: Price::new(900),
+            limits: Limits::default(),
+            trading_mode: TradingMode::OrderBook,
+            liquidity: None,
+        }
+    }
+}
+
+impl From<&OpinionModel> for MarketParams {
+    fn from(opinion: &OpinionModel) -> Self {
+        Self {
+            payout: opinion.payout,
+            tick_size: opinion.tick_size,
+            min_price: opinion.min_price,
+            max_price: opinion.max_price,
+            limits: Limits::from(opinion),
+            trading_mode: opinion.trading_mode.parse().unwrap_or_default(),
+            liquidity: opinion.liquidity,
+        }
+    }
+}
+
+impl MarketParams {
+    /// same rules as the checks on the opinions table, the band has to sit strictly inside the payout
+    pub fn check(&self) -> Result<(), &'static str> {
+        if self.tick_size <= Price::ZERO {
+            return Err("Tick size must be positive");
+        }
+        if self.min_price <= Price::ZERO
+            || self.min_price > self.max_price
+            || self.max_price >= self.payout
+        {
+            return Err("Prices must satisfy 0 < min price <= max price < payout");
+        }
+        let tick = self.tick_size.get();
+        if [self.payout, self.min_price, self.max_price]
+            .iter()
+            .any(|price| price.get() % tick != 0)
+        {
+            return Err("Payout, min price and max price must be multiples of the tick size");
+        }
+        if !self.limits.is_valid() {
+            return Err("Limits must be positive");
+        }
+        match (self.trading_mode, self.liquidity) {
+            (TradingMode::Amm, Some(liquidity)) if liquidity > Quantity::ZERO => {}
+            (TradingMode::OrderBook, None) => {}
+            (TradingMode::Amm, _) => return Err("A market maker needs a positive liquidity"),
+            (TradingMode::OrderBook, Some(_)) => {
+                return Err("Liquidity is only for markets with a market maker");
+            }
+        }
+        Ok(())
+    }
+
+    /// whether an order may be placed at `price` in this market
+    pub fn accepts(&self, price: Price) -> bool {
+        price >= self.min_price
+            && price <= self.max_price
+            && price.get() % self.tick_size.get() == 0
+    }
+
+    pub fn price_error(&self) -> String {
+        format!(
+            "Price must be between {} and {} in steps of {}",
+            self.min_price, self.max_price, self.tick_size
+        )
+    }
+
+    /// price a market order is held and matched at, a buy takes anything up to the top of the band
+    /// and a sell goes down to the bottom of it
+    pub fn market_order_price(&self, action: Action) -> Price {
+        match action {
+            Action::Buy => self.max_price,
+            Action::Sell => self.min_price,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Validate)]
 pub struct CreateOrderDto {
-    #[validate(range(min=1,max=5))]
-    pub quantity: u16,
-    #[validate(range(min=100,max=900))]
-    pub price: u16,
+    // the largest size is a limit of the market and the account
+    #[validate(range(min = Quantity::new(1)))]
+    pub quantity: Quantity,
+    // not needed for market orders, they always use the edge of the price band
+    // checked against the band and tick size of the market it is placed in
+    #[serde(default)]
+    pub price: Price,
 
     pub side: Side,
+    /// sell offers shares of `side` the user already holds
+    #[serde(default)]
+    pub action: Action,
+    #[serde(default)]
+    pub order_type: OrderType,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    /// only for orders that rest in the book, the unfilled part is cancelled at this time
+    pub expires_at: Option<DateTime<Utc>>,
+    /// overrides the account default for this order
+    pub self_trade_prevention: Option<SelfTradePrevention>,
+    /// id the user picks for the order, unique among their orders, a request sent again with it is not placed twice
+    /// the `Idempotency-Key` header sets it as well
+    #[validate(length(min = 1, max = 64))]
+    pub client_order_id: Option<String>,
+    /// makes it a conditional order, it waits outside the book until the market trades past the trigger
+    pub trigger: Option<Trigger>,
+    /// the order only ever adds liquidity, this says what happens if it would trade right away
+    pub post_only: Option<PostOnly>,
+    /// makes it an iceberg that shows only this much of its quantity in the depth at a time
+    pub display_quantity: Option<Quantity>,
+}
+
+impl CreateOrderDto {
+    /// only good-till-cancelled limit orders wait in the book, everything else gets its remainder cancelled
+    pub fn rests_in_book(&self) -> bool {
+        self.order_type == OrderType::Limit && self.time_in_force == TimeInForce::Gtc
+    }
+
+    pub fn to_order(&self, id: String, user_id: String) -> Order {
+        let mut order = Order::new(id, user_id, self.quantity, self.price, self.side.clone());
+        order.action = self.action;
+        order.expires_at = self.expires_at;
+        order.iceberg = self.display_quantity.map(|display| Iceberg {
+            display,
+            visible: display.min(self.quantity),
+        });
+        order.self_trade_prevention = self.self_trade_prevention.unwrap_or_default();
+        order.client_order_id = self.client_order_id.clone();
+        order.post_only = self.post_only;
+        order.time_in_force = self.time_in_force;
+        order
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OrderType {
+    #[default]
+    Limit,
+    /// sweeps the opposite side at any price, never rests in the book
+    Market,
+}
+
+impl OrderType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OrderType::Limit => "limit",
+            OrderType::Market => "market",
+        }
+    }
+}
+
+impl FromStr for OrderType {
+    type Err = String;
+
+    fn from_str(order_type: &str) -> Result<Self, Self::Err> {
+        match order_type {
+            "limit" => Ok(OrderType::Limit),
+            "market" => Ok(OrderType::Market),
+            _ => Err(format!("Unknown order type {}", order_type)),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeInForce {
+    /// good till cancelled, the remainder rests in the book
+    #[default]
+    Gtc,
+    /// immediate or cancel, fill what is possible and cancel the rest
+    Ioc,
+    /// fill or kill, fill the whole quantity right away or do nothing
+    Fok,
+}
+
+impl TimeInForce {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TimeInForce::Gtc => "gtc",
+            TimeInForce::Ioc => "ioc",
+            TimeInForce::Fok => "fok",
+        }
+    }
+}
+
+impl FromStr for TimeInForce {
+    type Err = String;
+
+    fn from_str(time_in_force: &str) -> Result<Self, Self::Err> {
+        match time_in_force {
+            "gtc" => Ok(TimeInForce::Gtc),
+            "ioc" => Ok(TimeInForce::Ioc),
+            "fok" => Ok(TimeInForce::Fok),
+            _ => Err(format!("Unknown time in force {}", time_in_force)),
+        }
+    }
+}
+
+/// new price and/or open quantity for a resting order, missing fields are kept as they are
+#[derive(Debug, Clone, Serialize, Deserialize, Validate)]
+pub struct AmendOrderDto {
+    #[validate(range(min = Quantity::new(1)))]
+    pub quantity: Option<Quantity>,
+    pub price: Option<Price>,
+}
+
+/// what a post only order does instead of trading with an order that rests already
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PostOnly {
+    /// the order is not placed
+    Reject,
+    /// the order rests one tick behind the best price of the other side
+    Reprice,
+}
+
+/// what happens when an incoming order meets a resting order of the same user
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfTradePrevention {
+    /// the incoming order stops there and its remainder is cancelled
+    #[default]
+    CancelNewest,
+    /// the resting order is cancelled and the incoming order keeps matching
+    CancelOldest,
+    /// both the resting order and the remainder of the incoming one are cancelled
+    CancelBoth,
+    /// both are reduced by the smaller quantity, whichever reaches zero is cancelled
+    DecrementAndCancel,
+}
+
+impl SelfTradePrevention {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SelfTradePrevention::CancelNewest => "cancel_newest",
+            SelfTradePrevention::CancelOldest => "cancel_oldest",
+            SelfTradePrevention::CancelBoth => "cancel_both",
+            SelfTradePrevention::DecrementAndCancel => "decrement_and_cancel",
+        }
+    }
+}
+
+impl FromStr for SelfTradePrevention {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "cancel_newest" => Ok(SelfTradePrevention::CancelNewest),
+            "cancel_oldest" => Ok(SelfTradePrevention::CancelOldest),
+            "cancel_both" => Ok(SelfTradePrevention::CancelBoth),
+            "decrement_and_cancel" => Ok(SelfTradePrevention::DecrementAndCancel),
+            _ => Err(format!("Unknown self trade prevention mode {}", mode)),
+        }
+    }
+}
+
+/// whether an order opens shares or sells shares the user holds
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Buy,
+    Sell,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Buy => "buy",
+            Action::Sell => "sell",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(action: &str) -> Result<Self, Self::Err> {
+        match action {
+            "buy" => Ok(Action::Buy),
+            "sell" => Ok(Action::Sell),
+            _ => Err(format!("Unknown action {}", action)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Side {
     Favour,
     Against,
 }
+
+impl Side {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Side::Favour => "favour",
+            Side::Against => "against",
+        }
+    }
+
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::Favour => Side::Against,
+            Side::Against => Side::Favour,
+        }
+    }
+}
+
+impl FromStr for Side {
+    type Err = String;
+
+    fn from_str(side: &str) -> Result<Self, Self::Err> {
+        match side {
+            "favour" => Ok(Side::Favour),
+            "against" => Ok(Side::Against),
+            _ => Err(format!("Unknown side {}", side)),
+        }
+    }
+}